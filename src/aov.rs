@@ -0,0 +1,271 @@
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable};
+use crate::image::Image;
+use crate::ray::Ray;
+use crate::sampler::{sample_key, Sampler, SamplerKind};
+use crate::vec3d::Colour;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Arbitrary output variable, giving data about the first surface seen through each pixel for
+/// compositing and denoising. Pixels seeing no surface are zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// colour of the surface, see `Material::albedo`
+    Albedo,
+    /// shading normal in world space
+    Normal,
+    /// hit point in world space
+    Position,
+    /// distance in front of the camera, in every channel
+    Depth,
+    /// movement of the surface on the image while the shutter is open, in pixels right and up,
+    /// zero for still surfaces seen by a still camera
+    Motion,
+    /// identifier of the object, numbering the objects of the scene from 1, in every channel
+    ObjectId,
+    /// identifier of the material, numbering materials from 1 in the order in which objects of
+    /// the scene use them, in every channel
+    MaterialId,
+}
+
+impl Aov {
+    pub fn parse(name: &str) -> Option<Aov> {
+        Some(match name {
+            "albedo" => Aov::Albedo,
+            "normal" => Aov::Normal,
+            "position" => Aov::Position,
+            "depth" => Aov::Depth,
+            "motion" => Aov::Motion,
+            "object" => Aov::ObjectId,
+            "material" => Aov::MaterialId,
+            _ => return None,
+        })
+    }
+    /// Whether values are identifiers, which are taken from a single sample as averaging them
+    /// would be meaningless.
+    fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+/// Output variables of a `width` x `height` image, each pixel (i, j) being at `j * width + i`
+/// with the first row at the bottom, like the film.
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    buffers: Vec<(Aov, Vec<Colour>)>,
+}
+
+impl AovBuffers {
+    /// Renders `aovs` by tracing `samples` camera rays through each pixel, made from the same
+    /// samples as the first ones the render takes so that the buffers line up with the image.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        aovs: &[Aov],
+        camera: &Camera,
+        world: &dyn Hittable,
+        sampler: SamplerKind,
+        samples_per_pixel: u32,
+        seed: u64,
+        samples: u32,
+        (width, height): (usize, usize),
+    ) -> AovBuffers {
+        let mut materials = Vec::new();
+        world.collect_materials(&mut materials);
+        // materials are told apart by address, as objects share them through `Arc`s
+        let mut material_ids = HashMap::new();
+        for material in &materials {
+            let next = material_ids.len() + 1;
+            material_ids
+                .entry(Arc::as_ptr(material) as usize)
+                .or_insert(next);
+        }
+
+        let rows = (0..height)
+            .into_par_iter()
+            .map(|h| {
+                let mut sampler = sampler.create(samples_per_pixel as usize, seed);
+                let sampler = &mut sampler;
+                let mut row = vec![vec![Colour::new(0., 0., 0.); width]; aovs.len()];
+                for w in 0..width {
+                    for i in 0..samples {
+                        sampler.start_pixel_sample((w, h), i as usize);
+                        let (du, dv) = sampler.get_2d();
+                        let r = camera
+                            .pixel_ray((w as f32 + du, h as f32 + dv), (width, height), sampler)
+                            .with_key(sample_key(seed, (w, h), i as usize));
+                        let mut rec = HitRecord::default();
+                        if !world.hit(&r, f32::EPSILON, f32::INFINITY, &mut rec) {
+                            continue;
+                        }
+                        let Some(material) = rec.material() else {
+                            continue;
+                        };
+                        for (aov, values) in aovs.iter().zip(row.iter_mut()) {
+                            if aov.is_id() && i > 0 {
+                                continue;
+                            }
+                            let scalar = |x: f32| Colour::new(x, x, x);
+                            let value = match aov {
+                                Aov::Albedo => material.albedo(&rec),
+                                Aov::Normal => material.shading_normal(&rec),
+                                Aov::Position => rec.p(),
+                                Aov::Depth => scalar(camera.depth(&rec.p())),
+                                Aov::Motion => motion(camera, &r, &rec, (width, height)),
+                                Aov::ObjectId => scalar(rec.object() as f32),
+                                Aov::MaterialId => {
+                                    let id = material_ids.get(&(Arc::as_ptr(material) as usize));
+                                    scalar(id.copied().unwrap_or(0) as f32)
+                                }
+                            };
+                            values[w] += if aov.is_id() {
+                                value
+                            } else {
+                                value / samples as f32
+                            };
+                        }
+                    }
+                }
+                row
+            })
+            .collect::<Vec<_>>();
+
+        let buffers = aovs
+            .iter()
+            .enumerate()
+            .map(|(k, &aov)| {
+                let values = rows.iter().flat_map(|row| row[k].iter().copied()).collect();
+                (aov, values)
+            })
+            .collect();
+        AovBuffers {
+            width,
+            height,
+            buffers,
+        }
+    }
+    pub fn get(&self, aov: Aov) -> Option<&[Colour]> {
+        self.buffers
+            .iter()
+            .find(|(kind, _)| *kind == aov)
+            .map(|(_, values)| values.as_slice())
+    }
+    /// Image of `aov`, if it was rendered.
+    pub fn image(&self, aov: Aov) -> Option<Image> {
+        let values = self.get(aov)?;
+        let pixels = values
+            .chunks(self.width)
+            .rev()
+            .flat_map(|row| row.iter().copied())
+            .collect();
+        Some(Image::new(self.width, self.height, pixels))
+    }
+}
+
+/// Movement on a `width` x `height` film of the surface point at `rec`, hit by `r`, from when
+/// the shutter opens to when it closes.
+fn motion(camera: &Camera, r: &Ray, rec: &HitRecord, size: (usize, usize)) -> Colour {
+    let (open, close) = camera.shutter();
+    let start = rec.p() - (r.time() - open) * rec.velocity();
+    let end = rec.p() + (close - r.time()) * rec.velocity();
+    match (
+        camera.film_position(&start, size),
+        camera.film_position(&end, size),
+    ) {
+        (Some(start), Some(end)) => Colour::new(end.0 - start.0, end.1 - start.1, 0.),
+        _ => Colour::new(0., 0., 0.),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{HittableList, Sphere};
+    use crate::material::Material;
+    use crate::vec3d::{Point3D, Vec3D};
+
+    const SIZE: (usize, usize) = (24, 8);
+
+    /// Renders `aovs` of three spheres in a row, the outer two sharing a material, with the
+    /// left one moving right by one unit while the shutter is open if `moving`.
+    fn render(aovs: &[Aov], moving: bool) -> AovBuffers {
+        let material = |albedo| Arc::new(Material::Lambertian { albedo });
+        let grey = material(Colour::new(0.5, 0.5, 0.5));
+        let red = material(Colour::new(0.8, 0.1, 0.1));
+        let left = Point3D::new(-2.2, 0., 0.);
+        let right = Point3D::new(2.2, 0., 0.);
+        let end = if moving {
+            left + Vec3D::new(1., 0., 0.)
+        } else {
+            left
+        };
+        let world = HittableList::new(vec![
+            Box::new(Sphere::moving(left, end, 0.8, Arc::clone(&grey))),
+            Box::new(Sphere::new(Point3D::new(0., 0., 0.), 0.8, red)),
+            Box::new(Sphere::new(right, 0.8, grey)),
+        ]);
+        let camera = Camera::new(
+            Point3D::new(0., 0., 5.),
+            Point3D::new(0., 0., 0.),
+            Vec3D::new(0., 1., 0.),
+            30.,
+            3.,
+            0.,
+            5.,
+            0.,
+            1.,
+        );
+        AovBuffers::render(
+            aovs,
+            &camera,
+            &world,
+            SamplerKind::Stratified,
+            16,
+            1,
+            4,
+            SIZE,
+        )
+    }
+
+    fn at(buffers: &AovBuffers, aov: Aov, (i, j): (usize, usize)) -> Colour {
+        buffers.get(aov).unwrap()[j * SIZE.0 + i]
+    }
+
+    #[test]
+    fn ids_number_objects_and_shared_materials() {
+        let buffers = render(&[Aov::ObjectId, Aov::MaterialId, Aov::Depth], false);
+        for (pixel, object, material) in [((5, 4), 1., 1.), ((12, 4), 2., 2.), ((18, 4), 3., 1.)] {
+            assert_eq!(at(&buffers, Aov::ObjectId, pixel).x(), object);
+            assert_eq!(at(&buffers, Aov::MaterialId, pixel).x(), material);
+        }
+        let depth = at(&buffers, Aov::Depth, (12, 4)).x();
+        // the front of the middle sphere is 4.2 away, and curves back a little within the pixel
+        assert!(depth > 4.2 && depth < 4.5, "{}", depth);
+        // nothing is seen in the corners
+        for aov in [Aov::ObjectId, Aov::MaterialId, Aov::Depth] {
+            assert_eq!(at(&buffers, aov, (0, 0)), Colour::new(0., 0., 0.));
+        }
+    }
+
+    #[test]
+    fn motion_follows_moving_objects_only() {
+        let still = render(&[Aov::Motion], false);
+        assert!(still
+            .get(Aov::Motion)
+            .unwrap()
+            .iter()
+            .all(|m| m.length() == 0.));
+
+        // a unit at the distance of the sphere spans about 3.3 pixels
+        let moving = render(&[Aov::Motion], true);
+        let motion = at(&moving, Aov::Motion, (6, 4));
+        assert!(
+            motion.x() > 3. && motion.x() < 4. && motion.y().abs() < 0.1,
+            "{:?}",
+            motion
+        );
+        assert_eq!(at(&moving, Aov::Motion, (12, 4)), Colour::new(0., 0., 0.));
+    }
+}
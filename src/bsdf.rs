@@ -0,0 +1,632 @@
+use crate::layered::LayeredBsdf;
+use crate::microfacet::{
+    fresnel_dielectric, ggx_d, ggx_g1, ggx_sample, reflect, refract, transmission_half_vector,
+};
+use crate::principled::PrincipledBsdf;
+use crate::vec3d::{Colour, Vec3D};
+use std::f32::consts;
+
+/// Direction sampled from a `Bsdf`, with the BSDF value and pdf for that direction.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vec3D,
+    pub f: Colour,
+    pub pdf: f32,
+    /// `wi` was picked from a delta lobe, so `f` and `pdf` are only meaningful as a ratio
+    pub specular: bool,
+    /// `wi` was picked from a diffuse lobe rather than a glossy or specular one
+    pub diffuse: bool,
+}
+
+/// Scattering function of a surface at a given hit point. All directions are unit vectors in
+/// the local shading frame, where the shading normal is +z and `wo` points away from the surface
+/// in the upper hemisphere.
+pub trait Bsdf {
+    /// Samples an incident direction given the uniform samples `uc` and `u`.
+    fn sample(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample>;
+    /// BSDF value for the given pair of directions. Zero for delta lobes.
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour;
+    /// Solid angle density with which `sample` returns `wi`. Zero for delta lobes.
+    fn pdf(&self, wo: &Vec3D, wi: &Vec3D) -> f32;
+    /// Whether the BSDF only has delta lobes, so that `eval` and `pdf` are always zero.
+    fn is_specular(&self) -> bool {
+        false
+    }
+    /// Widens lobes narrower than a GGX lobe of the given roughness, trading a little bias for
+    /// less noise where such lobes would only reflect light found by chance.
+    fn regularize(&mut self, _roughness: f32) {}
+}
+
+/// Any of the BSDFs a `Material` can produce, held by value so that building one per scattering
+/// event does not allocate. Only layered BSDFs box the BSDF they are made of.
+pub enum SurfaceBsdf {
+    Lambertian(LambertianBsdf),
+    OrenNayar(OrenNayarBsdf),
+    Metal(MetalBsdf),
+    Dielectric(DielectricBsdf),
+    Principled(PrincipledBsdf),
+    Layered(LayeredBsdf),
+}
+
+impl Bsdf for SurfaceBsdf {
+    fn sample(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        match self {
+            SurfaceBsdf::Lambertian(bsdf) => bsdf.sample(wo, uc, u),
+            SurfaceBsdf::OrenNayar(bsdf) => bsdf.sample(wo, uc, u),
+            SurfaceBsdf::Metal(bsdf) => bsdf.sample(wo, uc, u),
+            SurfaceBsdf::Dielectric(bsdf) => bsdf.sample(wo, uc, u),
+            SurfaceBsdf::Principled(bsdf) => bsdf.sample(wo, uc, u),
+            SurfaceBsdf::Layered(bsdf) => bsdf.sample(wo, uc, u),
+        }
+    }
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        match self {
+            SurfaceBsdf::Lambertian(bsdf) => bsdf.eval(wo, wi),
+            SurfaceBsdf::OrenNayar(bsdf) => bsdf.eval(wo, wi),
+            SurfaceBsdf::Metal(bsdf) => bsdf.eval(wo, wi),
+            SurfaceBsdf::Dielectric(bsdf) => bsdf.eval(wo, wi),
+            SurfaceBsdf::Principled(bsdf) => bsdf.eval(wo, wi),
+            SurfaceBsdf::Layered(bsdf) => bsdf.eval(wo, wi),
+        }
+    }
+    fn pdf(&self, wo: &Vec3D, wi: &Vec3D) -> f32 {
+        match self {
+            SurfaceBsdf::Lambertian(bsdf) => bsdf.pdf(wo, wi),
+            SurfaceBsdf::OrenNayar(bsdf) => bsdf.pdf(wo, wi),
+            SurfaceBsdf::Metal(bsdf) => bsdf.pdf(wo, wi),
+            SurfaceBsdf::Dielectric(bsdf) => bsdf.pdf(wo, wi),
+            SurfaceBsdf::Principled(bsdf) => bsdf.pdf(wo, wi),
+            SurfaceBsdf::Layered(bsdf) => bsdf.pdf(wo, wi),
+        }
+    }
+    fn is_specular(&self) -> bool {
+        match self {
+            SurfaceBsdf::Lambertian(bsdf) => bsdf.is_specular(),
+            SurfaceBsdf::OrenNayar(bsdf) => bsdf.is_specular(),
+            SurfaceBsdf::Metal(bsdf) => bsdf.is_specular(),
+            SurfaceBsdf::Dielectric(bsdf) => bsdf.is_specular(),
+            SurfaceBsdf::Principled(bsdf) => bsdf.is_specular(),
+            SurfaceBsdf::Layered(bsdf) => bsdf.is_specular(),
+        }
+    }
+    fn regularize(&mut self, roughness: f32) {
+        match self {
+            SurfaceBsdf::Lambertian(bsdf) => bsdf.regularize(roughness),
+            SurfaceBsdf::OrenNayar(bsdf) => bsdf.regularize(roughness),
+            SurfaceBsdf::Metal(bsdf) => bsdf.regularize(roughness),
+            SurfaceBsdf::Dielectric(bsdf) => bsdf.regularize(roughness),
+            SurfaceBsdf::Principled(bsdf) => bsdf.regularize(roughness),
+            SurfaceBsdf::Layered(bsdf) => bsdf.regularize(roughness),
+        }
+    }
+}
+
+pub struct LambertianBsdf {
+    pub albedo: Colour,
+}
+
+impl Bsdf for LambertianBsdf {
+    fn sample(&self, wo: &Vec3D, _uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let wi = cosine_hemisphere(u);
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi),
+            pdf,
+            specular: false,
+            diffuse: true,
+        })
+    }
+    fn eval(&self, _wo: &Vec3D, wi: &Vec3D) -> Colour {
+        if wi.z() <= 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        self.albedo * consts::FRAC_1_PI
+    }
+    fn pdf(&self, _wo: &Vec3D, wi: &Vec3D) -> f32 {
+        wi.z().max(0.) * consts::FRAC_1_PI
+    }
+}
+
+/// Rough diffuse reflection (Oren and Nayar, "Generalization of Lambert's Reflectance Model"),
+/// where `sigma` is the standard deviation of the microfacet slope angle in radians.
+pub struct OrenNayarBsdf {
+    pub albedo: Colour,
+    pub sigma: f32,
+}
+
+impl Bsdf for OrenNayarBsdf {
+    fn sample(&self, wo: &Vec3D, _uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let wi = cosine_hemisphere(u);
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi),
+            pdf,
+            specular: false,
+            diffuse: true,
+        })
+    }
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        if wi.z() <= 0. || wo.z() <= 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        let sigma2 = self.sigma * self.sigma;
+        let a = 1. - sigma2 / (2. * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        // the qualitative model reflects `a + b / 2` of the light arriving at grazing angles,
+        // which is its largest albedo and exceeds one for nearly smooth surfaces
+        let norm = (a + 0.5 * b).max(1.);
+
+        let sin_i = (1. - wi.z() * wi.z()).max(0.).sqrt();
+        let sin_o = (1. - wo.z() * wo.z()).max(0.).sqrt();
+        // cosine of the azimuthal angle between the two directions
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x() * wo.x() + wi.y() * wo.y()) / (sin_i * sin_o)).max(0.)
+        } else {
+            0.
+        };
+        // sin(alpha) * tan(beta), with alpha the larger of the two polar angles
+        let (sin_alpha, tan_beta) = if wi.z() < wo.z() {
+            (sin_i, sin_o / wo.z())
+        } else {
+            (sin_o, sin_i / wi.z())
+        };
+        self.albedo * consts::FRAC_1_PI * (a + b * cos_phi * sin_alpha * tan_beta) / norm
+    }
+    fn pdf(&self, _wo: &Vec3D, wi: &Vec3D) -> f32 {
+        wi.z().max(0.) * consts::FRAC_1_PI
+    }
+}
+
+/// Mirror reflection perturbed by a random offset of length up to `fuzziness`.
+pub struct MetalBsdf {
+    pub albedo: Colour,
+    pub fuzziness: f32,
+}
+
+impl MetalBsdf {
+    fn mirror(wo: &Vec3D) -> Vec3D {
+        Vec3D::new(-wo.x(), -wo.y(), wo.z())
+    }
+}
+
+impl Bsdf for MetalBsdf {
+    fn sample(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        if self.is_specular() {
+            let wi = MetalBsdf::mirror(wo);
+            return Some(BsdfSample {
+                wi,
+                f: self.albedo / wi.z(),
+                pdf: 1.,
+                specular: true,
+                diffuse: false,
+            });
+        }
+        // offset uniformly distributed in a ball of radius `fuzziness`
+        let z = 1. - 2. * u.0;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * consts::PI * u.1;
+        let offset = self.fuzziness * uc.cbrt() * Vec3D::new(r * phi.cos(), r * phi.sin(), z);
+        let wi = (MetalBsdf::mirror(wo) + offset).unit_vector();
+        if wi.z() <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            specular: false,
+            diffuse: false,
+        })
+    }
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        if wi.z() <= 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        // reflects all the light that is not scattered below the surface
+        self.albedo * self.pdf(wo, wi) / wi.z()
+    }
+    fn pdf(&self, wo: &Vec3D, wi: &Vec3D) -> f32 {
+        if self.is_specular() || wi.z() <= 0. {
+            return 0.;
+        }
+        // density of directions going through the ball around the mirror direction, found from
+        // the length of `wi` that lies inside of it
+        let b = wi.dot(&MetalBsdf::mirror(wo));
+        let discriminant = b * b - 1. + self.fuzziness * self.fuzziness;
+        if discriminant <= 0. {
+            return 0.;
+        }
+        let far = b + discriminant.sqrt();
+        let near = (b - discriminant.sqrt()).max(0.);
+        if far <= 0. {
+            return 0.;
+        }
+        (far.powi(3) - near.powi(3)) / (4. * consts::PI * self.fuzziness.powi(3))
+    }
+    fn is_specular(&self) -> bool {
+        self.fuzziness == 0.
+    }
+    /// The fuzz ball is matched to the GGX lobe by their median deflection: GGX normals have a
+    /// median tilt of `alpha` (deflecting the mirror direction by about `2 * alpha`), while a
+    /// point uniformly distributed in a ball of radius `f` lies a median `0.608 * f` off its
+    /// centre sideways. The tails of the two lobes still differ.
+    fn regularize(&mut self, roughness: f32) {
+        let alpha = roughness * roughness;
+        self.fuzziness = self.fuzziness.max(2. / 0.608 * alpha);
+    }
+}
+
+/// Glass, choosing between reflection and refraction with the exact Fresnel reflectance. Rough
+/// glass scatters off GGX microfacets (Walter et al. 2007), leaving out the `1 / eta^2` radiance
+/// scaling on transmission like the smooth case does, so that it tends to smooth glass as its
+/// roughness goes to zero.
+pub struct DielectricBsdf {
+    /// ratio of the refractive index on the far side of the surface to the near side
+    pub eta: f32,
+    /// GGX roughness, zero for smooth glass
+    pub roughness: f32,
+}
+
+impl DielectricBsdf {
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(0.001)
+    }
+
+    fn sample_rough(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let alpha = self.alpha();
+        let m = ggx_sample(alpha, alpha, u.0, u.1);
+        let reflected = uc < fresnel_dielectric(wo.dot(&m), self.eta);
+        let wi = if reflected {
+            reflect(wo, &m)
+        } else {
+            refract(wo, &m, self.eta)?
+        };
+        // the pdf only accounts for reflections staying above the surface and refractions going
+        // below it
+        if reflected != (wi.z() > 0.) {
+            return None;
+        }
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi),
+            pdf,
+            specular: false,
+            diffuse: false,
+        })
+    }
+}
+
+impl Bsdf for DielectricBsdf {
+    fn sample(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        if !self.is_specular() {
+            return self.sample_rough(wo, uc, u);
+        }
+        let reflected = Vec3D::new(-wo.x(), -wo.y(), wo.z());
+        let reflect_prob = fresnel_dielectric(wo.z(), self.eta);
+        let (wi, pdf) = if uc < reflect_prob {
+            (reflected, reflect_prob)
+        } else {
+            let refracted = (-wo).refract(&Vec3D::new(0., 0., 1.), 1. / self.eta);
+            (refracted.unit_vector(), 1. - reflect_prob)
+        };
+        Some(BsdfSample {
+            wi,
+            f: Colour::new(pdf, pdf, pdf) / wi.z().abs(),
+            pdf,
+            specular: true,
+            diffuse: false,
+        })
+    }
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        if self.is_specular() || wo.z() <= 0. || wi.z() == 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        let alpha = self.alpha();
+        let value = if wi.z() > 0. {
+            let h = (wo + wi).unit_vector();
+            fresnel_dielectric(wo.dot(&h), self.eta)
+                * ggx_d(&h, alpha, alpha)
+                * ggx_g1(wo, alpha, alpha)
+                * ggx_g1(wi, alpha, alpha)
+                / (4. * wo.z() * wi.z())
+        } else {
+            let m = match transmission_half_vector(wo, wi, self.eta) {
+                Some(m) => m,
+                None => return Colour::new(0., 0., 0.),
+            };
+            let (cos_om, cos_im) = (wo.dot(&m), wi.dot(&m));
+            let denom = (cos_im + cos_om / self.eta).powi(2) * wi.z().abs() * wo.z();
+            (1. - fresnel_dielectric(cos_om, self.eta))
+                * ggx_d(&m, alpha, alpha)
+                * ggx_g1(wo, alpha, alpha)
+                * ggx_g1(wi, alpha, alpha)
+                * (cos_im * cos_om / denom).abs()
+        };
+        Colour::new(value, value, value)
+    }
+    fn pdf(&self, wo: &Vec3D, wi: &Vec3D) -> f32 {
+        if self.is_specular() || wo.z() <= 0. || wi.z() == 0. {
+            return 0.;
+        }
+        let alpha = self.alpha();
+        if wi.z() > 0. {
+            let h = (wo + wi).unit_vector();
+            let cos_oh = wo.dot(&h);
+            return fresnel_dielectric(cos_oh, self.eta) * ggx_d(&h, alpha, alpha) * h.z()
+                / (4. * cos_oh);
+        }
+        match transmission_half_vector(wo, wi, self.eta) {
+            Some(m) => {
+                let (cos_om, cos_im) = (wo.dot(&m), wi.dot(&m));
+                let jacobian = cos_im.abs() / (cos_im + cos_om / self.eta).powi(2);
+                (1. - fresnel_dielectric(cos_om, self.eta))
+                    * ggx_d(&m, alpha, alpha)
+                    * m.z()
+                    * jacobian
+            }
+            None => 0.,
+        }
+    }
+    fn is_specular(&self) -> bool {
+        self.roughness == 0.
+    }
+    fn regularize(&mut self, roughness: f32) {
+        self.roughness = self.roughness.max(roughness);
+    }
+}
+
+/// Samples a direction in the upper hemisphere with density `z / PI`, by projecting a uniformly
+/// distributed point on the unit disk up onto the hemisphere (Malley's method).
+pub fn cosine_hemisphere(u: (f32, f32)) -> Vec3D {
+    let r = u.0.sqrt();
+    let phi = 2. * consts::PI * u.1;
+    Vec3D::new(r * phi.cos(), r * phi.sin(), (1. - u.0).max(0.).sqrt())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::sampler::{sample_sphere, seeded_rng};
+
+    const SAMPLES: usize = 500_000;
+
+    #[derive(Default)]
+    struct Sum([f64; 3]);
+
+    impl Sum {
+        fn add(&mut self, value: Colour) {
+            for c in 0..3 {
+                self.0[c] += value[c] as f64;
+            }
+        }
+        fn scaled(&self, scale: f64) -> Colour {
+            let [x, y, z] = self.0.map(|sum| (sum * scale) as f32);
+            Colour::new(x, y, z)
+        }
+    }
+
+    /// Directions `wo` the BSDFs are checked from, from normal to grazing incidence.
+    pub fn outgoing_directions() -> Vec<Vec3D> {
+        [1f32, 0.7, 0.2]
+            .iter()
+            .map(|&z| Vec3D::new((1. - z * z).sqrt(), 0., z))
+            .collect()
+    }
+
+    /// Checks that `sample` agrees with `eval` and `pdf`, that `pdf` integrates to the fraction of
+    /// samples that succeed, and that the albedo estimated by importance sampling matches the one
+    /// estimated by uniform sampling and does not exceed 1. Returns the albedo.
+    pub fn check_bsdf(bsdf: &dyn Bsdf, wo: &Vec3D) -> Colour {
+        check_bsdf_within(bsdf, wo, 1.)
+    }
+
+    /// `check_bsdf` for BSDFs whose albedo may reach `max_albedo`, such as those scaling the
+    /// radiance of light leaving a denser medium.
+    pub fn check_bsdf_within(bsdf: &dyn Bsdf, wo: &Vec3D, max_albedo: f32) -> Colour {
+        let rng = seeded_rng(7);
+        // sums are kept in double precision, as single precision would drift over this many
+        // samples
+        let mut sampled = Sum::default();
+        let mut found = 0;
+        for _ in 0..SAMPLES {
+            let Some(sample) = bsdf.sample(wo, rng.f32(), (rng.f32(), rng.f32())) else {
+                continue;
+            };
+            assert!(!sample.specular);
+            assert!(sample.pdf > 0.);
+            let (f, pdf) = (bsdf.eval(wo, &sample.wi), bsdf.pdf(wo, &sample.wi));
+            assert!(
+                (f - sample.f).length() <= 1e-3 * f.length(),
+                "{:?} {:?}",
+                f,
+                sample.f
+            );
+            assert!(
+                (pdf - sample.pdf).abs() <= 1e-3 * pdf,
+                "{} {}",
+                pdf,
+                sample.pdf
+            );
+            sampled.add(sample.f * sample.wi.z().abs() / sample.pdf);
+            found += 1;
+        }
+        let sampled = sampled.scaled(1. / SAMPLES as f64);
+
+        let mut uniform = Sum::default();
+        let mut pdf_integral = Sum::default();
+        for _ in 0..SAMPLES {
+            let wi = sample_sphere((rng.f32(), rng.f32()));
+            uniform.add(bsdf.eval(wo, &wi) * wi.z().abs());
+            let pdf = bsdf.pdf(wo, &wi);
+            pdf_integral.add(Colour::new(pdf, pdf, pdf));
+        }
+        let scale = 4. * std::f64::consts::PI / SAMPLES as f64;
+        let uniform = uniform.scaled(scale);
+        let pdf_integral = pdf_integral.scaled(scale).x();
+
+        let found = found as f32 / SAMPLES as f32;
+        assert!(
+            (pdf_integral - found).abs() < 0.03,
+            "{} {}",
+            pdf_integral,
+            found
+        );
+        for (s, u) in [
+            (sampled.x(), uniform.x()),
+            (sampled.y(), uniform.y()),
+            (sampled.z(), uniform.z()),
+        ] {
+            assert!((s - u).abs() < 0.03, "{:?} {:?}", sampled, uniform);
+            assert!(s <= max_albedo + 0.005, "{:?}", sampled);
+        }
+        sampled
+    }
+
+    #[test]
+    fn lambertian_is_consistent() {
+        let bsdf = LambertianBsdf {
+            albedo: Colour::new(0.9, 0.5, 0.1),
+        };
+        for wo in outgoing_directions() {
+            let albedo = check_bsdf(&bsdf, &wo);
+            assert!((albedo - bsdf.albedo).length() < 5e-3);
+        }
+    }
+
+    #[test]
+    fn oren_nayar_is_consistent() {
+        for sigma in [0.2, 0.5, 1.] {
+            let bsdf = OrenNayarBsdf {
+                albedo: Colour::new(1., 1., 1.),
+                sigma,
+            };
+            // nearly smooth surfaces reflect the most light at grazing angles
+            let grazing = Vec3D::new((1. - 0.05f32 * 0.05).sqrt(), 0., 0.05);
+            for wo in outgoing_directions().into_iter().chain([grazing]) {
+                check_bsdf(&bsdf, &wo);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        let albedo = Colour::new(0.9, 0.5, 0.1);
+        let oren_nayar = OrenNayarBsdf { albedo, sigma: 0. };
+        let lambertian = LambertianBsdf { albedo };
+        let rng = seeded_rng(7);
+        for wo in outgoing_directions() {
+            for _ in 0..100 {
+                let wi = cosine_hemisphere((rng.f32(), rng.f32()));
+                let difference = oren_nayar.eval(&wo, &wi) - lambertian.eval(&wo, &wi);
+                assert!(difference.length() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn metal_is_consistent() {
+        for fuzziness in [0.3, 0.6, 1.] {
+            let bsdf = MetalBsdf {
+                albedo: Colour::new(1., 1., 1.),
+                fuzziness,
+            };
+            for wo in outgoing_directions() {
+                check_bsdf(&bsdf, &wo);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_dielectric_splits_light_between_reflection_and_refraction() {
+        for eta in [1.5, 1. / 1.5] {
+            let bsdf = DielectricBsdf { eta, roughness: 0. };
+            assert!(bsdf.is_specular());
+            for wo in outgoing_directions() {
+                let rng = seeded_rng(7);
+                for _ in 0..1000 {
+                    let uc = rng.f32();
+                    let sample = bsdf.sample(&wo, uc, (rng.f32(), rng.f32())).unwrap();
+                    assert!(sample.specular);
+                    assert!((sample.wi.length() - 1.).abs() < 1e-4);
+                    let weight = sample.f * sample.wi.z().abs() / sample.pdf;
+                    assert!((weight - Colour::new(1., 1., 1.)).length() < 1e-4);
+                    assert_eq!(bsdf.eval(&wo, &sample.wi).length(), 0.);
+                    assert_eq!(bsdf.pdf(&wo, &sample.wi), 0.);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_is_consistent() {
+        for eta in [1.5, 1. / 1.5] {
+            for roughness in [0.5, 0.8] {
+                let bsdf = DielectricBsdf { eta, roughness };
+                for wo in outgoing_directions() {
+                    check_bsdf(&bsdf, &wo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearly_smooth_dielectric_reflects_like_smooth_glass() {
+        let samples = 100_000;
+        let reflected_fraction = |bsdf: &DielectricBsdf, wo: &Vec3D| {
+            let rng = seeded_rng(7);
+            let reflected = (0..samples)
+                .filter_map(|_| bsdf.sample(wo, rng.f32(), (rng.f32(), rng.f32())))
+                .filter(|sample| sample.wi.z() > 0.)
+                .count();
+            reflected as f32 / samples as f32
+        };
+        for eta in [1.5, 1. / 1.5] {
+            let smooth = DielectricBsdf { eta, roughness: 0. };
+            let rough = DielectricBsdf {
+                eta,
+                roughness: 0.01,
+            };
+            for wo in outgoing_directions() {
+                let (a, b) = (
+                    reflected_fraction(&smooth, &wo),
+                    reflected_fraction(&rough, &wo),
+                );
+                assert!((a - b).abs() < 0.01, "{} {:?} {} {}", eta, wo, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn regularization_roughens_smooth_lobes() {
+        let mut glass = DielectricBsdf {
+            eta: 1.5,
+            roughness: 0.,
+        };
+        glass.regularize(0.3);
+        assert!(!glass.is_specular());
+        assert_eq!(glass.roughness, 0.3);
+
+        let mut metal = MetalBsdf {
+            albedo: Colour::new(1., 1., 1.),
+            fuzziness: 0.,
+        };
+        metal.regularize(0.3);
+        assert!(!metal.is_specular());
+        // a GGX roughness of 0.3 deflects the mirror direction by about 0.18 radians
+        assert!((0.608 * metal.fuzziness - 2. * 0.09).abs() < 1e-4);
+        metal.regularize(0.1);
+        assert!((0.608 * metal.fuzziness - 2. * 0.09).abs() < 1e-4);
+    }
+}
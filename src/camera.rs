@@ -1,5 +1,5 @@
 use crate::ray::Ray;
-use crate::utils::rand_in_range;
+use crate::sampler::{sample_disk, Sampler};
 use crate::vec3d::{Point3D, Vec3D};
 use std::f32::consts;
 
@@ -15,6 +15,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         origin: Point3D,
         lookat: Point3D,
@@ -53,13 +54,56 @@ impl Camera {
             time1,
         }
     }
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd: Vec3D = self.lens_radius * Vec3D::random_in_unit_disk();
+    /// Distance of `p` in front of the camera, along the direction it looks in.
+    pub fn depth(&self, p: &Point3D) -> f32 {
+        (*p - self.origin).dot(&-self.axes[2])
+    }
+    /// Ray through the point (`x`, `y`) of a `width` x `height` film, in pixels from its bottom
+    /// left corner.
+    pub fn pixel_ray(
+        &self,
+        (x, y): (f32, f32),
+        size: (usize, usize),
+        sampler: &mut dyn Sampler,
+    ) -> Ray {
+        let (scale_x, scale_y) = film_scale(size);
+        self.get_ray(x / scale_x, y / scale_y, sampler)
+    }
+    /// Point of a `width` x `height` film where `p` is seen from the centre of the lens, the
+    /// inverse of `pixel_ray`, if it is in front of the camera.
+    pub fn film_position(&self, p: &Point3D, size: (usize, usize)) -> Option<(f32, f32)> {
+        let depth = self.depth(p);
+        if depth <= 0. {
+            return None;
+        }
+        let focus_dist = (self.origin - self.lower_left_corner).dot(&self.axes[2]);
+        let on_viewport =
+            (*p - self.origin) * (focus_dist / depth) - (self.lower_left_corner - self.origin);
+        let (scale_x, scale_y) = film_scale(size);
+        Some((
+            scale_x * on_viewport.dot(&self.horizontal) / self.horizontal.length_squared(),
+            scale_y * on_viewport.dot(&self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+    /// Time at which the shutter opens and closes.
+    pub fn shutter(&self) -> (f32, f32) {
+        (self.time0, self.time1)
+    }
+    /// Ray through the point (`s`, `t`) of the viewport, drawing the point on the lens and the
+    /// time from `sampler`.
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd: Vec3D = self.lens_radius * sample_disk(sampler.get_2d());
         let offset: Vec3D = self.axes[0] * rd.x() + self.axes[1] * rd.y();
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            rand_in_range(self.time0, self.time1),
+            self.time0 + (self.time1 - self.time0) * sampler.get_1d(),
         )
     }
 }
+
+/// Pixels per unit of viewport coordinates on a `width` x `height` film, whose first and last
+/// pixels are at the edges of the viewport.
+fn film_scale((width, height): (usize, usize)) -> (f32, f32) {
+    ((width.max(2) - 1) as f32, (height.max(2) - 1) as f32)
+}
@@ -0,0 +1,281 @@
+use crate::options::Options;
+use crate::scene::Scene;
+use crate::utils::RunningStats;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &str = "traycer checkpoint 1";
+
+/// State of an unfinished render, from which it can be resumed to give the same image as if it
+/// had never stopped.
+///
+/// The file starts with the settings of the render as text lines of a name and a value, ended by
+/// `end`, followed by the samples of each pass completed by every tile and the state of each
+/// pixel, row by row, in little endian: the fixed-point film sums then the sample statistics.
+pub struct Checkpoint {
+    /// settings affecting the image, as names and values
+    pub config: Vec<(String, String)>,
+    /// samples per pixel taken by every pixel once the pass under way is done
+    pub samples_taken: u32,
+    /// film sums of each pixel
+    pub sums: Vec<[i64; 4]>,
+    /// statistics of the samples taken in each pixel
+    pub stats: Vec<RunningStats>,
+}
+
+impl Checkpoint {
+    /// Settings of a `width` x `height` render of `scene` with `options` that change the image it
+    /// gives.
+    pub fn config(
+        options: &Options,
+        scene: &Scene,
+        width: usize,
+        height: usize,
+    ) -> Vec<(String, String)> {
+        let name = options
+            .scene
+            .as_ref()
+            .map_or("random".to_string(), |path| path.display().to_string());
+        vec![
+            ("width".to_string(), width.to_string()),
+            ("height".to_string(), height.to_string()),
+            ("scene".to_string(), name),
+            (
+                "scene_digest".to_string(),
+                scene
+                    .digest
+                    .map_or("none".to_string(), |digest| format!("{:016x}", digest)),
+            ),
+            ("spectral".to_string(), options.spectral.to_string()),
+            ("sampler".to_string(), format!("{:?}", options.sampler)),
+            ("seed".to_string(), options.seed.to_string()),
+            ("filter".to_string(), format!("{:?}", options.filter)),
+            ("spp".to_string(), options.samples_per_pixel.to_string()),
+            (
+                "adaptive".to_string(),
+                format!("{:?}", options.adaptive_threshold),
+            ),
+            (
+                "min_spp".to_string(),
+                options.min_samples_per_pixel.to_string(),
+            ),
+            ("max_depth".to_string(), options.max_depth.to_string()),
+            (
+                "diffuse_bounces".to_string(),
+                format!("{:?}", options.diffuse_bounces),
+            ),
+            (
+                "glossy_bounces".to_string(),
+                format!("{:?}", options.glossy_bounces),
+            ),
+            (
+                "transmission_bounces".to_string(),
+                format!("{:?}", options.transmission_bounces),
+            ),
+            (
+                "volume_bounces".to_string(),
+                format!("{:?}", options.volume_bounces),
+            ),
+            (
+                "roulette_depth".to_string(),
+                format!("{:?}", options.roulette_depth),
+            ),
+            (
+                "clamp_direct".to_string(),
+                format!("{:?}", options.clamp_direct),
+            ),
+            (
+                "clamp_indirect".to_string(),
+                format!("{:?}", options.clamp_indirect),
+            ),
+            (
+                "regularize".to_string(),
+                format!("{:?}", options.regularize),
+            ),
+        ]
+    }
+    /// Reads the checkpoint of a render of `pixels` pixels, which must have been started with the
+    /// settings `config` to be resumed with them.
+    pub fn read<P: AsRef<Path>>(
+        path: P,
+        config: &[(String, String)],
+        pixels: usize,
+    ) -> io::Result<Checkpoint> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut input = BufReader::new(File::open(path)?);
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        if line.trim_end() != MAGIC {
+            return Err(invalid("not a checkpoint"));
+        }
+        let mut saved = Vec::new();
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(invalid("truncated settings"));
+            }
+            let line = line.trim_end();
+            if line == "end" {
+                break;
+            }
+            let (name, value) = line.split_once(' ').ok_or_else(|| invalid("bad setting"))?;
+            saved.push((name.to_string(), value.to_string()));
+        }
+        check_config(&saved, config).map_err(|msg| invalid(&msg))?;
+        let samples_taken = u32::from_le_bytes(read_bytes(&mut input)?);
+        let mut sums = Vec::with_capacity(pixels);
+        let mut stats = Vec::with_capacity(pixels);
+        for _ in 0..pixels {
+            let mut pixel_sums = [0; 4];
+            for sum in &mut pixel_sums {
+                *sum = i64::from_le_bytes(read_bytes(&mut input)?);
+            }
+            sums.push(pixel_sums);
+            let count = u32::from_le_bytes(read_bytes(&mut input)?);
+            let mean = f64::from_le_bytes(read_bytes(&mut input)?);
+            let m2 = f64::from_le_bytes(read_bytes(&mut input)?);
+            stats.push(RunningStats::from_parts(count, mean, m2));
+        }
+        if input.read(&mut [0])? != 0 {
+            return Err(invalid("unexpected data after the pixels"));
+        }
+        Ok(Checkpoint {
+            config: saved,
+            samples_taken,
+            sums,
+            stats,
+        })
+    }
+    /// Writes the checkpoint to `path` through a temporary file, so that an interrupted write
+    /// leaves the previous checkpoint intact.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut out = BufWriter::new(File::create(&temporary)?);
+        writeln!(out, "{}", MAGIC)?;
+        for (name, value) in &self.config {
+            writeln!(out, "{} {}", name, value)?;
+        }
+        writeln!(out, "end")?;
+        out.write_all(&self.samples_taken.to_le_bytes())?;
+        for (sums, stats) in self.sums.iter().zip(&self.stats) {
+            for sum in sums {
+                out.write_all(&sum.to_le_bytes())?;
+            }
+            let (count, mean, m2) = stats.parts();
+            out.write_all(&count.to_le_bytes())?;
+            out.write_all(&mean.to_le_bytes())?;
+            out.write_all(&m2.to_le_bytes())?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// Checks that a render started with the settings `saved` can be resumed with `config`.
+fn check_config(saved: &[(String, String)], config: &[(String, String)]) -> Result<(), String> {
+    for (name, value) in config {
+        match saved.iter().find(|(saved, _)| saved == name) {
+            Some((_, saved)) if saved == value => {}
+            Some((_, saved)) => {
+                return Err(format!(
+                    "checkpoint has {} `{}` but the render has `{}`",
+                    name, saved, value
+                ))
+            }
+            None => return Err(format!("checkpoint has no {}", name)),
+        }
+    }
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("traycer-{}-{}", std::process::id(), name))
+    }
+
+    fn load_scene(name: &str, contents: &str) -> (Options, Scene) {
+        let path = temporary_path(name);
+        fs::write(&path, contents).unwrap();
+        let scene = Scene::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let options = Options {
+            scene: Some(path),
+            ..Options::default()
+        };
+        (options, scene)
+    }
+
+    fn checkpoint(config: Vec<(String, String)>) -> Checkpoint {
+        let mut stats = RunningStats::default();
+        stats.add(0.25);
+        stats.add(4.);
+        Checkpoint {
+            config,
+            samples_taken: 12,
+            sums: vec![[1, -2, 3, 1 << 40], [0, 0, 0, 0]],
+            stats: vec![stats, RunningStats::default()],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let (options, scene) =
+            load_scene("round_trip.scene", "material m lambertian 0.5 0.5 0.5\n");
+        let config = Checkpoint::config(&options, &scene, 2, 1);
+        let path = temporary_path("round_trip.checkpoint");
+        let saved = checkpoint(config.clone());
+        saved.write(&path).unwrap();
+        let read = Checkpoint::read(&path, &config, 2);
+        fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+        assert_eq!(read.config, saved.config);
+        assert_eq!(read.samples_taken, saved.samples_taken);
+        assert_eq!(read.sums, saved.sums);
+        let parts = |stats: &[RunningStats]| stats.iter().map(|s| s.parts()).collect::<Vec<_>>();
+        assert_eq!(parts(&read.stats), parts(&saved.stats));
+    }
+
+    #[test]
+    fn mismatched_settings_are_rejected() {
+        let contents = "material m lambertian 0.5 0.5 0.5\n";
+        let (options, scene) = load_scene("mismatch.scene", contents);
+        let config = Checkpoint::config(&options, &scene, 2, 1);
+        let path = temporary_path("mismatch.checkpoint");
+        checkpoint(config.clone()).write(&path).unwrap();
+
+        let reseeded = Options {
+            scene: options.scene.clone(),
+            seed: options.seed + 1,
+            ..Options::default()
+        };
+        let (_, edited) = load_scene("mismatch.scene", "material m lambertian 0.6 0.5 0.5\n");
+        let results = [
+            Checkpoint::read(&path, &Checkpoint::config(&reseeded, &scene, 2, 1), 2),
+            Checkpoint::read(&path, &Checkpoint::config(&options, &edited, 2, 1), 2),
+            Checkpoint::read(&path, &Checkpoint::config(&options, &scene, 1, 2), 2),
+            Checkpoint::read(&path, &config, 3),
+        ];
+        fs::remove_file(&path).unwrap();
+        let errors: Vec<_> = results
+            .iter()
+            .map(|result| result.as_ref().err().unwrap().to_string())
+            .collect();
+        assert!(errors[0].contains("seed"), "{}", errors[0]);
+        assert!(errors[1].contains("scene_digest"), "{}", errors[1]);
+        assert!(errors[2].contains("width"), "{}", errors[2]);
+        assert!(!errors[3].is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::spectrum::rgb_to_xyz;
+use crate::vec3d::{Colour, Vec3D};
+use rayon::prelude::*;
+
+/// Weights of the 5-tap B3 spline kernel, from the centre outwards.
+const KERNEL: [f32; 3] = [3. / 8., 1. / 4., 1. / 16.];
+/// Exponent of the cosine between normals weighting taps, higher values keeping edges between
+/// surfaces facing different ways sharper.
+const NORMAL_POWER: i32 = 16;
+/// Difference in albedo over which the weight of taps falls by a factor of e.
+const SIGMA_ALBEDO: f32 = 0.3;
+/// Difference in depth, relative to how much it changes across a surface over the distance of a
+/// tap, over which the weight of taps falls by a factor of e.
+const SIGMA_DEPTH: f32 = 2.;
+
+/// Edge-avoiding à-trous wavelet filter, following Dammertz et al., "Edge-Avoiding À-Trous
+/// Wavelet Transform for fast Global Illumination Filtering", with the variance guidance of
+/// Schied et al., "Spatiotemporal Variance-Guided Filtering".
+///
+/// Each iteration blurs with a sparse 5x5 kernel whose taps spread twice as far as in the
+/// previous one. Taps are weighted down across edges in the albedo, normal and depth buffers,
+/// and where the luminance differs by more than the noise expected from the variance of the
+/// pixel, so that the filter smooths noise without smoothing the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    pub iterations: u32,
+    /// scale of the luminance differences attributed to noise, higher values blurring more
+    pub strength: f32,
+}
+
+/// Per-pixel buffers guiding the filter, laid out like the image.
+pub struct Features<'a> {
+    pub albedo: &'a [Colour],
+    pub normal: &'a [Vec3D],
+    /// distance in front of the camera, zero where nothing was hit
+    pub depth: &'a [f32],
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            strength: 1.,
+        }
+    }
+}
+
+impl Denoiser {
+    /// Filters the `width` x `height` image `colour`, `variance` being the variance of the
+    /// estimate of the luminance of each pixel.
+    pub fn denoise(
+        &self,
+        (width, height): (usize, usize),
+        colour: &[Colour],
+        variance: &[f32],
+        features: &Features,
+    ) -> Vec<Colour> {
+        // filtering the illumination rather than the colour keeps the texture of surfaces sharp
+        let albedo = features
+            .albedo
+            .iter()
+            .map(|albedo| {
+                Colour::new(
+                    albedo.x().max(0.01),
+                    albedo.y().max(0.01),
+                    albedo.z().max(0.01),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut illumination = colour
+            .iter()
+            .zip(&albedo)
+            .map(|(colour, albedo)| *colour / *albedo)
+            .collect::<Vec<_>>();
+        let mut variance = variance
+            .iter()
+            .zip(&albedo)
+            .map(|(variance, albedo)| variance / luminance(albedo).powi(2))
+            .collect::<Vec<_>>();
+        let depth_gradient = depth_gradient((width, height), features.depth);
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let blurred_variance = blur_3x3((width, height), &variance);
+            let (next_illumination, next_variance) = (0..width * height)
+                .into_par_iter()
+                .map(|p| {
+                    let (i, j) = (p % width, p / width);
+                    let luminance_p = luminance(&illumination[p]);
+                    let sigma_luminance = 4. * self.strength * blurred_variance[p].max(0.).sqrt();
+                    let mut sum = Colour::new(0., 0., 0.);
+                    let mut sum_variance = 0.;
+                    let mut sum_weight = 0.;
+                    for dy in -2..=2isize {
+                        for dx in -2..=2isize {
+                            let x = i as isize + dx * step;
+                            let y = j as isize + dy * step;
+                            if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+                                continue;
+                            }
+                            let q = y as usize * width + x as usize;
+                            let kernel = KERNEL[dx.unsigned_abs()] * KERNEL[dy.unsigned_abs()];
+                            let distance = ((dx * dx + dy * dy) as f32).sqrt() * step as f32;
+                            let weight = kernel
+                                * (-(luminance_p - luminance(&illumination[q])).abs()
+                                    / (sigma_luminance + 1e-4))
+                                    .exp()
+                                * normal_weight(&features.normal[p], &features.normal[q])
+                                * (-(features.depth[p] - features.depth[q]).abs()
+                                    / (SIGMA_DEPTH * depth_gradient[p] * distance + 1e-3))
+                                    .exp()
+                                * (-max_difference(&albedo[p], &albedo[q]) / SIGMA_ALBEDO).exp();
+                            sum += weight * illumination[q];
+                            sum_variance += weight * weight * variance[q];
+                            sum_weight += weight;
+                        }
+                    }
+                    // the centre tap has full weight, so the sum is never zero
+                    (sum / sum_weight, sum_variance / (sum_weight * sum_weight))
+                })
+                .unzip();
+            illumination = next_illumination;
+            variance = next_variance;
+        }
+
+        illumination
+            .iter()
+            .zip(&albedo)
+            .map(|(illumination, albedo)| *illumination * *albedo)
+            .collect()
+    }
+}
+
+fn luminance(colour: &Colour) -> f32 {
+    rgb_to_xyz(colour).y()
+}
+
+fn max_difference(a: &Colour, b: &Colour) -> f32 {
+    let d = *a - *b;
+    d.x().abs().max(d.y().abs()).max(d.z().abs())
+}
+
+/// Weight between pixels facing in the directions `n` and `m`, zero for pixels with normals
+/// and without.
+fn normal_weight(n: &Vec3D, m: &Vec3D) -> f32 {
+    match (n.length_squared() > 0., m.length_squared() > 0.) {
+        (true, true) => n
+            .unit_vector()
+            .dot(&m.unit_vector())
+            .max(0.)
+            .powi(NORMAL_POWER),
+        (false, false) => 1.,
+        _ => 0.,
+    }
+}
+
+/// How much depth is expected to change per pixel across the surface seen by each pixel. Along
+/// each axis, this is the smaller change to the neighbours on either side, as at the edge of a
+/// surface the change to the neighbour on the other side says nothing about the surface.
+fn depth_gradient((width, height): (usize, usize), depth: &[f32]) -> Vec<f32> {
+    (0..width * height)
+        .into_par_iter()
+        .map(|p| {
+            let (i, j) = (p % width, p / width);
+            let z = depth[p];
+            let change = |neighbours: [Option<usize>; 2]| {
+                neighbours
+                    .iter()
+                    .flatten()
+                    .map(|&q| (depth[q] - z).abs())
+                    .fold(f32::INFINITY, f32::min)
+            };
+            let dx = change([
+                i.checked_sub(1).map(|i| j * width + i),
+                Some(i + 1).filter(|&i| i < width).map(|i| j * width + i),
+            ]);
+            let dy = change([
+                j.checked_sub(1).map(|j| j * width + i),
+                Some(j + 1).filter(|&j| j < height).map(|j| j * width + i),
+            ]);
+            // images one pixel wide or high have no neighbours along that axis
+            [dx, dy]
+                .iter()
+                .filter(|d| d.is_finite())
+                .fold(0., |a: f32, &b| a.max(b))
+        })
+        .collect()
+}
+
+/// Blurs `values` with a 3x3 Gaussian, steadying variance estimates made from few samples.
+fn blur_3x3((width, height): (usize, usize), values: &[f32]) -> Vec<f32> {
+    const WEIGHTS: [f32; 2] = [1. / 2., 1. / 4.];
+    (0..width * height)
+        .into_par_iter()
+        .map(|p| {
+            let (i, j) = (p % width, p / width);
+            let mut sum = 0.;
+            let mut sum_weight = 0.;
+            for dy in -1..=1isize {
+                for dx in -1..=1isize {
+                    let x = i as isize + dx;
+                    let y = j as isize + dy;
+                    if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+                        continue;
+                    }
+                    let weight = WEIGHTS[dx.unsigned_abs()] * WEIGHTS[dy.unsigned_abs()];
+                    sum += weight * values[y as usize * width + x as usize];
+                    sum_weight += weight;
+                }
+            }
+            sum / sum_weight
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::seeded_rng;
+
+    const SIZE: (usize, usize) = (32, 32);
+
+    fn up() -> Vec3D {
+        Vec3D::new(0., 0., 1.)
+    }
+
+    fn grey(x: f32) -> Colour {
+        Colour::new(x, x, x)
+    }
+
+    /// Denoises `colour` over a surface split down the middle, whose halves have the given
+    /// normals and depths.
+    fn denoise(
+        colour: &[Colour],
+        variance: f32,
+        normals: [Vec3D; 2],
+        depths: [f32; 2],
+    ) -> Vec<Colour> {
+        let half = |p: usize| usize::from(p % SIZE.0 >= SIZE.0 / 2);
+        let n = SIZE.0 * SIZE.1;
+        let albedo = vec![grey(1.); n];
+        let normal: Vec<_> = (0..n).map(|p| normals[half(p)]).collect();
+        let depth: Vec<_> = (0..n).map(|p| depths[half(p)]).collect();
+        Denoiser::default().denoise(
+            SIZE,
+            colour,
+            &vec![variance; n],
+            &Features {
+                albedo: &albedo,
+                normal: &normal,
+                depth: &depth,
+            },
+        )
+    }
+
+    #[test]
+    fn flat_images_are_unchanged() {
+        let colour = vec![Colour::new(0.2, 0.4, 0.6); SIZE.0 * SIZE.1];
+        for result in denoise(&colour, 0., [up(), up()], [2., 2.]) {
+            assert!((result - colour[0]).length() < 1e-5, "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn noise_on_flat_surfaces_is_reduced() {
+        let rng = seeded_rng(3);
+        let colour: Vec<_> = (0..SIZE.0 * SIZE.1)
+            .map(|_| grey(0.5 + 0.2 * (rng.f32() - 0.5)))
+            .collect();
+        let error = |image: &[Colour]| -> f32 {
+            image.iter().map(|c| (c.x() - 0.5).powi(2)).sum::<f32>() / image.len() as f32
+        };
+        let variance = 0.04 / 12.;
+        let result = denoise(&colour, variance, [up(), up()], [2., 2.]);
+        assert!(error(&result) < 0.1 * error(&colour));
+    }
+
+    #[test]
+    fn edges_are_not_blurred_across() {
+        // the halves differ in colour by less than the noise expected from the variance, so
+        // only the normals and depths keep them apart
+        let colour: Vec<_> = (0..SIZE.0 * SIZE.1)
+            .map(|p| grey(if p % SIZE.0 >= SIZE.0 / 2 { 0.8 } else { 0.2 }))
+            .collect();
+        let side = Vec3D::new(1., 0., 0.);
+        for (normals, depths) in [([up(), up()], [2., 6.]), ([up(), side], [2., 2.])] {
+            let result = denoise(&colour, 1., normals, depths);
+            let worst = result
+                .iter()
+                .zip(&colour)
+                .map(|(a, b)| (*a - *b).length())
+                .fold(0., f32::max);
+            assert!(worst < 1e-3, "{:?} {:?} {}", normals, depths, worst);
+        }
+    }
+}
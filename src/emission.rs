@@ -0,0 +1,106 @@
+use crate::spectrum::{
+    blackbody_xyz, cie_xyz, planck, rgb_to_xyz, xyz_to_rgb, Wavelengths, LAMBDA_MAX, LAMBDA_MIN,
+};
+use crate::vec3d::Colour;
+
+/// Light given off by a surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Emission {
+    rgb: Colour,
+    blackbody: Option<Blackbody>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Blackbody {
+    temperature: f32,
+    /// scale bringing the Planckian spectrum to the requested luminance
+    scale: f32,
+}
+
+impl Emission {
+    pub fn rgb(rgb: Colour) -> Emission {
+        Emission {
+            rgb,
+            blackbody: None,
+        }
+    }
+    /// Light from a black body at `temperature` kelvin, with luminance `intensity`.
+    pub fn blackbody(temperature: f32, intensity: f32) -> Emission {
+        let xyz = blackbody_xyz(temperature);
+        // luminance of the unnormalized spectrum, with the same normalization as
+        // `Wavelengths::to_rgb`
+        let mut luminance = 0.;
+        let mut y_integral = 0.;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let y = cie_xyz(lambda).y();
+            luminance += planck(lambda, temperature) * y;
+            y_integral += y;
+            lambda += 1.;
+        }
+        let scale = if luminance > 0. {
+            intensity * y_integral / luminance
+        } else {
+            0.
+        };
+        Emission {
+            rgb: intensity * into_gamut(xyz_to_rgb(&xyz)),
+            blackbody: Some(Blackbody { temperature, scale }),
+        }
+    }
+    /// Emitted radiance, at the given wavelengths when rendering spectrally.
+    pub fn radiance(&self, wavelengths: Option<&Wavelengths>) -> Colour {
+        match (wavelengths, self.blackbody) {
+            (Some(wavelengths), Some(blackbody)) => {
+                let l = |i| blackbody.scale * planck(wavelengths.lambda(i), blackbody.temperature);
+                Colour::new(l(0), l(1), l(2))
+            }
+            (Some(wavelengths), None) => wavelengths.illuminant(&self.rgb),
+            (None, _) => self.rgb,
+        }
+    }
+}
+
+/// Desaturates `rgb` towards grey of the same luminance until none of its channels is negative,
+/// bringing colours outside the sRGB gamut, like black bodies below about 1500 K, to the edge of
+/// it.
+fn into_gamut(rgb: Colour) -> Colour {
+    let luminance = rgb_to_xyz(&rgb).y().max(0.);
+    let grey = Colour::new(luminance, luminance, luminance);
+    let min = rgb.x().min(rgb.y()).min(rgb.z());
+    if min >= 0. {
+        return rgb;
+    }
+    grey + luminance / (luminance - min) * (rgb - grey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colour(temperature: f32) -> Colour {
+        Emission::blackbody(temperature, 1.).radiance(None)
+    }
+
+    #[test]
+    fn blackbody_near_d65_is_white() {
+        // D65 lies a little off the Planckian locus
+        let rgb = colour(6500.);
+        for c in 0..3 {
+            assert!((rgb[c] - 1.).abs() < 0.05, "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn blackbodies_redden_as_they_cool() {
+        let mut previous = colour(12000.);
+        for temperature in [8000., 5000., 3000., 2000., 1500., 1000., 800.] {
+            let rgb = colour(temperature);
+            assert!(rgb.x() >= 0. && rgb.y() >= 0. && rgb.z() >= 0.);
+            assert!((rgb_to_xyz(&rgb).y() - 1.).abs() < 1e-3);
+            // blue reaches zero once the colour leaves the gamut
+            assert!(rgb.x() > previous.x() && rgb.z() <= previous.z());
+            previous = rgb;
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::filter::Filter;
+use crate::tile::Tile;
+use crate::vec3d::Colour;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Number of fixed-point steps per unit of radiance in the film.
+const FIXED_POINT_SCALE: f32 = (1 << 20) as f32;
+/// Largest magnitude stored for a single contribution, keeping sums far from overflowing.
+const MAX_CONTRIBUTION: f32 = 1e9;
+
+/// Accumulates the samples of a render into pixels, each sample being splatted with the weight
+/// of the filter onto every pixel whose centre lies within the filter radius. Sums are kept in
+/// fixed point, as integer additions give the same result in any order, so that renders do not
+/// depend on how threads interleave.
+///
+/// A film can cover only part of the image, starting at the pixel (`x0`, `y0`), so that workers
+/// can accumulate a tile into a film of their own and merge it into the image once done.
+pub struct Film {
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    filter: Filter,
+    /// factor making the weights a sample is splatted with integrate to 1
+    weight_scale: f32,
+    pixels: Vec<FilmPixel>,
+}
+
+#[derive(Default)]
+struct FilmPixel {
+    /// filter-weighted sum of the samples
+    rgb: [AtomicI64; 3],
+    /// sum of the filter weights
+    weight: AtomicI64,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Film {
+        Film::with_bounds(0, 0, width, height, filter, 1. / filter.integral())
+    }
+    fn with_bounds(
+        x0: usize,
+        y0: usize,
+        width: usize,
+        height: usize,
+        filter: Filter,
+        weight_scale: f32,
+    ) -> Film {
+        Film {
+            x0,
+            y0,
+            width,
+            height,
+            filter,
+            weight_scale,
+            pixels: (0..width * height).map(|_| FilmPixel::default()).collect(),
+        }
+    }
+    /// Empty film covering the pixels that samples taken in `tile` contribute to.
+    pub fn for_tile(&self, tile: &Tile) -> Film {
+        let margin = (self.filter.radius() + 0.5).ceil() as usize;
+        let x0 = tile.x0.saturating_sub(margin).max(self.x0);
+        let y0 = tile.y0.saturating_sub(margin).max(self.y0);
+        let x1 = (tile.x1 + margin).min(self.x0 + self.width);
+        let y1 = (tile.y1 + margin).min(self.y0 + self.height);
+        Film::with_bounds(x0, y0, x1 - x0, y1 - y0, self.filter, self.weight_scale)
+    }
+    /// Adds the sums accumulated in `other`, which must lie within this film.
+    pub fn merge(&self, other: &Film) {
+        for j in 0..other.height {
+            for i in 0..other.width {
+                let source = &other.pixels[j * other.width + i];
+                let pixel = self.pixel_at(other.x0 + i, other.y0 + j);
+                for c in 0..3 {
+                    pixel.rgb[c]
+                        .fetch_add(source.rgb[c].load(Ordering::Relaxed), Ordering::Relaxed);
+                }
+                pixel
+                    .weight
+                    .fetch_add(source.weight.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }
+    }
+    /// Adds a sample at the raster position (`x`, `y`), where the pixel (i, j) covers
+    /// [i, i + 1) x [j, j + 1).
+    pub fn add_sample(&self, x: f32, y: f32, radiance: &Colour) {
+        let radius = self.filter.radius();
+        // pixels whose centre lies within the filter radius
+        let x0 = ((x - 0.5 - radius).ceil() as isize).max(self.x0 as isize);
+        let y0 = ((y - 0.5 - radius).ceil() as isize).max(self.y0 as isize);
+        let x1 = ((x - 0.5 + radius).floor() as isize).min((self.x0 + self.width) as isize - 1);
+        let y1 = ((y - 0.5 + radius).floor() as isize).min((self.y0 + self.height) as isize - 1);
+        for j in y0..=y1 {
+            for i in x0..=x1 {
+                let weight = self.weight_scale
+                    * self.filter.evaluate(i as f32 + 0.5 - x, j as f32 + 0.5 - y);
+                if weight == 0. {
+                    continue;
+                }
+                let pixel = self.pixel_at(i as usize, j as usize);
+                for c in 0..3 {
+                    pixel.rgb[c].fetch_add(to_fixed(weight * radiance[c]), Ordering::Relaxed);
+                }
+                pixel.weight.fetch_add(to_fixed(weight), Ordering::Relaxed);
+            }
+        }
+    }
+    /// Filtered value of the pixel (`i`, `j`).
+    pub fn pixel(&self, i: usize, j: usize) -> Colour {
+        let pixel = self.pixel_at(i, j);
+        let weight = pixel.weight.load(Ordering::Relaxed);
+        if weight <= 0 {
+            return Colour::new(0., 0., 0.);
+        }
+        let value = |c: usize| (pixel.rgb[c].load(Ordering::Relaxed) as f64 / weight as f64) as f32;
+        Colour::new(value(0), value(1), value(2))
+    }
+    /// Filtered values of every pixel, row by row from the bottom.
+    pub fn pixels(&self) -> Vec<Colour> {
+        (self.y0..self.y0 + self.height)
+            .flat_map(|j| (self.x0..self.x0 + self.width).map(move |i| (i, j)))
+            .map(|(i, j)| self.pixel(i, j))
+            .collect()
+    }
+    /// Fixed-point sums of the red, green and blue samples and of the weights of each pixel, row
+    /// by row.
+    pub fn sums(&self) -> Vec<[i64; 4]> {
+        self.pixels
+            .iter()
+            .map(|pixel| {
+                let load = |sum: &AtomicI64| sum.load(Ordering::Relaxed);
+                [
+                    load(&pixel.rgb[0]),
+                    load(&pixel.rgb[1]),
+                    load(&pixel.rgb[2]),
+                    load(&pixel.weight),
+                ]
+            })
+            .collect()
+    }
+    /// Replaces the sums of every pixel with those from `sums`.
+    pub fn set_sums(&self, sums: &[[i64; 4]]) {
+        assert_eq!(sums.len(), self.pixels.len());
+        for (pixel, sums) in self.pixels.iter().zip(sums) {
+            for (sum, &value) in pixel.rgb.iter().zip(sums) {
+                sum.store(value, Ordering::Relaxed);
+            }
+            pixel.weight.store(sums[3], Ordering::Relaxed);
+        }
+    }
+    fn pixel_at(&self, i: usize, j: usize) -> &FilmPixel {
+        &self.pixels[(j - self.y0) * self.width + i - self.x0]
+    }
+}
+
+/// Converts to fixed point, dropping values that are not finite.
+fn to_fixed(x: f32) -> i64 {
+    if !x.is_finite() {
+        return 0;
+    }
+    (x.clamp(-MAX_CONTRIBUTION, MAX_CONTRIBUTION) * FIXED_POINT_SCALE) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splatted_weights_integrate_to_one() {
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter = Filter::parse(name, None).unwrap();
+            let film = Film::new(9, 9, filter);
+            // samples on a fine grid covering every position that reaches the central pixel
+            let steps_per_pixel = 32;
+            let step = 1. / steps_per_pixel as f32;
+            for j in 0..9 * steps_per_pixel {
+                for i in 0..9 * steps_per_pixel {
+                    let (x, y) = ((i as f32 + 0.5) * step, (j as f32 + 0.5) * step);
+                    film.add_sample(x, y, &Colour::new(1., 1., 1.));
+                }
+            }
+            let weight = film.sums()[4 * 9 + 4][3] as f32 / FIXED_POINT_SCALE;
+            assert!(
+                (weight * step * step - 1.).abs() < 0.01,
+                "{} {}",
+                name,
+                weight
+            );
+            assert!((film.pixel(4, 4) - Colour::new(1., 1., 1.)).length() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::f32::consts;
+
+/// Reconstruction filter weighting the samples around each pixel centre. Filters are separable,
+/// the weight of a sample being the product of the 1D filter along each axis.
+#[derive(Debug, Clone, Copy)]
+pub enum Filter {
+    Box {
+        radius: f32,
+    },
+    Tent {
+        radius: f32,
+    },
+    /// Gaussian of standard deviation `sigma`, shifted down to reach zero at `radius`.
+    Gaussian {
+        radius: f32,
+        sigma: f32,
+    },
+    /// Cubic from Mitchell and Netravali, "Reconstruction Filters in Computer Graphics", with
+    /// negative lobes sharpening the image. The paper recommends `b = c = 1/3`.
+    Mitchell {
+        radius: f32,
+        b: f32,
+        c: f32,
+    },
+    /// Sinc windowed by a wider sinc reaching zero at `radius`, which is the number of lobes of
+    /// the sinc that are kept.
+    Lanczos {
+        radius: f32,
+    },
+}
+
+impl Filter {
+    /// Filter with the given name, using `radius` in pixels or a default suited to the filter.
+    pub fn parse(name: &str, radius: Option<f32>) -> Result<Filter, String> {
+        if let Some(radius) = radius {
+            if !(radius > 0. && radius.is_finite()) {
+                return Err(format!("filter radius must be positive, got `{}`", radius));
+            }
+        }
+        Ok(match name {
+            "box" => Filter::Box {
+                radius: radius.unwrap_or(0.5),
+            },
+            "tent" => Filter::Tent {
+                radius: radius.unwrap_or(1.),
+            },
+            "gaussian" => {
+                let radius = radius.unwrap_or(1.5);
+                Filter::Gaussian {
+                    radius,
+                    sigma: radius / 3.,
+                }
+            }
+            "mitchell" => Filter::Mitchell {
+                radius: radius.unwrap_or(2.),
+                b: 1. / 3.,
+                c: 1. / 3.,
+            },
+            "lanczos" => Filter::Lanczos {
+                radius: radius.unwrap_or(3.),
+            },
+            _ => return Err(format!("unknown filter `{}`", name)),
+        })
+    }
+    /// Distance from the pixel centre beyond which samples have no weight.
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+    /// Integral of the weights over the plane.
+    pub fn integral(&self) -> f32 {
+        // filters are separable, and smooth enough for the midpoint rule
+        const STEPS: usize = 1024;
+        let radius = self.radius();
+        let dx = 2. * radius / STEPS as f32;
+        let integral_1d = (0..STEPS)
+            .map(|i| self.evaluate_1d(-radius + (i as f32 + 0.5) * dx))
+            .sum::<f32>()
+            * dx;
+        integral_1d * integral_1d
+    }
+    /// Weight of a sample at offset (`x`, `y`) from the pixel centre, up to the scale given by
+    /// `integral`.
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match *self {
+            Filter::Box { radius } => {
+                if x <= radius {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Filter::Tent { radius } => (radius - x).max(0.),
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f32| (-x * x / (2. * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2. * x / radius;
+                if x < 1. {
+                    ((12. - 9. * b - 6. * c) * x * x * x
+                        + (-18. + 12. * b + 6. * c) * x * x
+                        + (6. - 2. * b))
+                        / 6.
+                } else if x < 2. {
+                    ((-b - 6. * c) * x * x * x
+                        + (6. * b + 30. * c) * x * x
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    0.
+                }
+            }
+            Filter::Lanczos { radius } => {
+                if x > radius {
+                    0.
+                } else {
+                    sinc(x) * sinc(x / radius)
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.;
+    }
+    (consts::PI * x).sin() / (consts::PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+    #[test]
+    fn filters_vanish_at_their_radius() {
+        for name in NAMES.iter().skip(1) {
+            for radius in [1., 2.5] {
+                let filter = Filter::parse(name, Some(radius)).unwrap();
+                assert!(filter.evaluate(0., 0.) > 0., "{}", name);
+                for (x, y) in [(radius, 0.), (0., -radius), (radius, radius)] {
+                    assert!(filter.evaluate(x, y).abs() < 1e-5, "{} {}", name, x);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_filters_are_rejected() {
+        assert!(Filter::parse("sinc", None).is_err());
+        for radius in [0., -1., f32::NAN, f32::INFINITY] {
+            assert!(Filter::parse("tent", Some(radius)).is_err(), "{}", radius);
+        }
+        assert_eq!(Filter::parse("lanczos", Some(2.)).unwrap().radius(), 2.);
+    }
+}
@@ -1,14 +1,25 @@
+use crate::material::Material;
 use crate::ray::Ray;
+use crate::stats;
 use crate::vec3d::*;
-//use std::sync::Arc;
-use crate::material::Material;
+use std::f32::consts;
+use std::sync::Arc;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone)]
 pub struct HitRecord {
     p: Point3D,
     t: f32,
+    u: f32,
+    v: f32,
     normal: Vec3D,
+    shading_normal: Vec3D,
+    dpdu: Vec3D,
+    dpdv: Vec3D,
+    velocity: Vec3D,
     front_face: bool,
+    material: Option<Arc<Material>>,
+    /// one more than the index of the object hit in the scene, zero meaning none
+    object: usize,
 }
 
 impl HitRecord {
@@ -19,45 +30,133 @@ impl HitRecord {
         } else {
             -*outward_normal
         };
+        self.shading_normal = self.normal;
         assert!(self.normal().dot(&r.direction()) <= 0.);
     }
+    /// Replaces the shading normal, flipping it if needed so that it is on the same side of the
+    /// surface as the geometric normal.
+    pub fn set_shading_normal(&mut self, shading_normal: &Vec3D) {
+        self.shading_normal = if shading_normal.dot(&self.normal) < 0. {
+            -*shading_normal
+        } else {
+            *shading_normal
+        };
+    }
     pub fn p(&self) -> Point3D {
         self.p
     }
     pub fn t(&self) -> f32 {
         self.t
     }
+    /// Surface coordinates of the hit point, used for texture lookups.
+    pub fn u(&self) -> f32 {
+        self.u
+    }
+    pub fn v(&self) -> f32 {
+        self.v
+    }
+    /// Normal of the actual surface, facing against the incoming ray.
     pub fn normal(&self) -> Vec3D {
         self.normal
     }
+    /// Normal used for shading, which may be perturbed by normal or bump mapping.
+    pub fn shading_normal(&self) -> Vec3D {
+        self.shading_normal
+    }
+    /// Derivatives of the hit point with respect to `u` and `v`.
+    pub fn dpdu(&self) -> Vec3D {
+        self.dpdu
+    }
+    pub fn dpdv(&self) -> Vec3D {
+        self.dpdv
+    }
+    /// Velocity of the surface at the hit point, in distance per unit of time.
+    pub fn velocity(&self) -> Vec3D {
+        self.velocity
+    }
     pub fn front_face(&self) -> bool {
         self.front_face
     }
+    /// Material of the surface that was hit.
+    pub fn material(&self) -> Option<&Arc<Material>> {
+        self.material.as_ref()
+    }
+    /// Identifier of the object that was hit, numbering the objects of the scene from 1.
+    pub fn object(&self) -> usize {
+        self.object
+    }
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> Option<Material>;
+    /// Finds the closest hit of `r` between `t_min` and `t_max`, skipping the parts of surfaces
+    /// cut out by their material, and fills `rec` with it.
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool;
+    /// Adds the materials of the object to `materials`, in a stable order.
+    fn collect_materials(&self, materials: &mut Vec<Arc<Material>>);
 }
 
 pub struct Sphere {
+    /// centre at time zero
     center: Point3D,
+    velocity: Vec3D,
     radius: f32,
-    material: Material,
+    material: Arc<Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Point3D, radius: f32, material: Material) -> Sphere {
+    pub fn new(center: Point3D, radius: f32, material: Arc<Material>) -> Sphere {
+        Sphere::moving(center, center, radius, material)
+    }
+    /// Sphere moving in a straight line from `center0` at time 0 to `center1` at time 1.
+    pub fn moving(
+        center0: Point3D,
+        center1: Point3D,
+        radius: f32,
+        material: Arc<Material>,
+    ) -> Sphere {
         Sphere {
-            center,
+            center: center0,
+            velocity: center1 - center0,
             radius,
             material,
         }
     }
+    fn center(&self, time: f32) -> Point3D {
+        self.center + time * self.velocity
+    }
+    fn set_surface(&self, r: &Ray, rec: &mut HitRecord) {
+        rec.p = r.at(rec.t);
+        rec.velocity = self.velocity;
+        let outward_normal: Vec3D = (rec.p - self.center(r.time())) / self.radius;
+        rec.set_normal_face(r, &outward_normal);
+        let (u, v) = Sphere::get_uv(&outward_normal);
+        rec.u = u;
+        rec.v = v;
+        // derivatives of the mapping in `get_uv`, which are degenerate at the poles, where
+        // `dpdu` is kept pointing along the meridian picked by `u` so that tangent frames stay
+        // well defined
+        let (x, y, z) = (outward_normal.x(), outward_normal.y(), outward_normal.z());
+        let sin_theta = (x * x + z * z).sqrt().max(1e-6);
+        let phi = 2. * consts::PI * u - consts::PI;
+        rec.dpdu =
+            2. * consts::PI * self.radius * sin_theta * Vec3D::new(-phi.sin(), 0., -phi.cos());
+        rec.dpdv = consts::PI
+            * self.radius
+            * Vec3D::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta);
+    }
+    /// Maps a point on the unit sphere to (u, v) in [0, 1], with v running from the bottom pole
+    /// to the top one.
+    fn get_uv(p: &Point3D) -> (f32, f32) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + consts::PI;
+        (phi / (2. * consts::PI), theta / consts::PI)
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> Option<Material> {
-        let sep: Vec3D = r.origin() - self.center;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        stats::count_intersection_test();
+        let sep: Vec3D = r.origin() - self.center(r.time());
         let a: f32 = r.direction().length_squared();
         let half_b: f32 = r.direction().dot(&sep);
         let c: f32 = sep.length_squared() - self.radius * self.radius;
@@ -65,24 +164,22 @@ impl Hittable for Sphere {
 
         if discriminant > 0. {
             let root: f32 = discriminant.sqrt();
-            let temp = (-half_b - root) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.at(rec.t);
-                let outward_normal: Vec3D = (rec.p - self.center) / self.radius;
-                rec.set_normal_face(r, &outward_normal);
-                return Some(self.material);
-            }
-            let temp = (-half_b + root) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.at(rec.t);
-                let outward_normal: Vec3D = (rec.p - self.center) / self.radius;
-                rec.set_normal_face(r, &outward_normal);
-                return Some(self.material);
+            for temp in [(-half_b - root) / a, (-half_b + root) / a] {
+                if temp < t_max && temp > t_min {
+                    rec.t = temp;
+                    self.set_surface(r, rec);
+                    // cut out parts of the surface let the ray through to whatever is behind
+                    if self.material.is_opaque_at(r, rec) {
+                        rec.material = Some(Arc::clone(&self.material));
+                        return true;
+                    }
+                }
             }
         }
-        None
+        false
+    }
+    fn collect_materials(&self, materials: &mut Vec<Arc<Material>>) {
+        materials.push(Arc::clone(&self.material));
     }
 }
 
@@ -97,99 +194,72 @@ impl HittableList {
     pub fn add(&mut self, object: Box<dyn Hittable>) {
         self.objects.push(object)
     }
-    pub fn clear(&mut self) {
-        self.objects.clear()
-    }
-    pub fn random_scene() -> HittableList {
-        let ground_radius = 300.;
-        let mut objects: Vec<Box<dyn Hittable>> = vec![];
-        let ground = Box::new(Sphere::new(
-            Point3D::new(0., -ground_radius, 0.),
-            ground_radius,
-            Material::Lambertian {
-                albedo: Colour::new(0.5, 0.5, 0.5),
-            },
-        ));
-        objects.push(ground);
-        (-11..11).into_iter().for_each(|a| {
-            (-11..11).into_iter().for_each(|b| {
-                let choose_mat = fastrand::f32();
-                let radius = 0.15 + 0.1 * fastrand::f32();
-                let x = a as f32 + 0.9 * fastrand::f32();
-                let y = b as f32 + 0.9 * fastrand::f32();
-                // offset so that the spheres stick to the ground
-                let offset = (ground_radius.powi(2) - x.powi(2) - y.powi(2)).sqrt();
-                let z = -ground_radius + offset + radius;
-                let center = Point3D::new(x, z, y);
-                if (center - Point3D::new(4., 0.2, 0.)).length() > 0.9 {
-                    if choose_mat < 0.5 {
-                        // lambertian
-                        objects.push(Box::new(Sphere::new(
-                            center,
-                            radius,
-                            Material::Lambertian {
-                                albedo: Colour::random(0., 1.),
-                            },
-                        )));
-                    } else if choose_mat < 0.85 {
-                        // metal
-                        objects.push(Box::new(Sphere::new(
-                            center,
-                            radius,
-                            Material::Metal {
-                                albedo: Colour::random(0., 1.),
-                                fuzziness: 0.5 * fastrand::f32(),
-                            },
-                        )));
-                    } else {
-                        // dielectric
-                        objects.push(Box::new(Sphere::new(
-                            center,
-                            radius,
-                            Material::Dielectric { refr_index: 1.5 },
-                        )));
-                    }
-                }
-            })
-        });
-        objects.push(Box::new(Sphere::new(
-            Point3D::new(-4., 1., 0.),
-            1.,
-            Material::Lambertian {
-                albedo: Colour::new(0.4, 0.2, 0.1),
-            },
-        )));
-        objects.push(Box::new(Sphere::new(
-            Point3D::new(4., 1., 0.),
-            1.,
-            Material::Metal {
-                albedo: Colour::new(0.7, 0.6, 0.5),
-                fuzziness: 0.,
-            },
-        )));
-        objects.push(Box::new(Sphere::new(
-            Point3D::new(0., 1., 0.),
-            1.,
-            Material::Dielectric { refr_index: 1.5 },
-        )));
-
-        HittableList { objects }
-    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> Option<Material> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
         let mut temp_rec = HitRecord::default();
-        let mut hit_anything = None;
+        let mut hit_anything = false;
         let mut closest_so_far = t_max;
 
-        for obj in self.objects.iter() {
-            if let Some(material) = obj.hit(r, t_min, closest_so_far, &mut temp_rec) {
-                hit_anything = Some(material);
+        for (i, obj) in self.objects.iter().enumerate() {
+            if obj.hit(r, t_min, closest_so_far, &mut temp_rec) {
+                hit_anything = true;
                 closest_so_far = temp_rec.t;
-                *rec = temp_rec;
+                temp_rec.object = i + 1;
+                *rec = temp_rec.clone();
             }
         }
         hit_anything
     }
+    fn collect_materials(&self, materials: &mut Vec<Arc<Material>>) {
+        for obj in &self.objects {
+            obj.collect_materials(materials);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit_sphere(origin: Point3D, direction: Vec3D) -> HitRecord {
+        let sphere = Sphere::new(
+            Point3D::new(0., 0., 0.),
+            2.,
+            Arc::new(Material::Lambertian {
+                albedo: Colour::new(0.5, 0.5, 0.5),
+            }),
+        );
+        let mut rec = HitRecord::default();
+        assert!(sphere.hit(
+            &Ray::new(origin, direction, 0.),
+            0.001,
+            f32::INFINITY,
+            &mut rec
+        ));
+        rec
+    }
+
+    #[test]
+    fn sphere_derivatives_are_tangent() {
+        for direction in [
+            Vec3D::new(-1., 0.2, 0.1),
+            Vec3D::new(0.3, -1., -0.4),
+            Vec3D::new(0.1, 0.5, -1.),
+        ] {
+            let rec = hit_sphere(-5. * direction, direction);
+            assert!(rec.dpdu().dot(&rec.normal()).abs() < 1e-4);
+            assert!(rec.dpdv().dot(&rec.normal()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sphere_tangent_is_defined_at_the_poles() {
+        for y in [5., -5.] {
+            let rec = hit_sphere(Point3D::new(0., y, 0.), Vec3D::new(0., -y, 0.));
+            assert!(rec.dpdu().length() > 0.);
+            assert!(rec.dpdu().y().abs() < 1e-6);
+        }
+    }
 }
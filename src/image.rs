@@ -0,0 +1,192 @@
+use crate::vec3d::Colour;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// RGB image stored row by row from the top, with channel values in [0, 1] unless it holds data
+/// such as normals or depths.
+#[derive(Debug)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Colour>,
+}
+
+impl Image {
+    /// Image from its pixels, given row by row from the top.
+    pub fn new(width: usize, height: usize, pixels: Vec<Colour>) -> Image {
+        assert_eq!(pixels.len(), width * height);
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+    /// Reads a binary (P6) or plain (P3) PPM file. Values are used as stored, without any gamma
+    /// decoding.
+    pub fn read_ppm<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let bytes = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let magic = match bytes.get(..2) {
+            Some(b"P3") => 3,
+            Some(b"P6") => 6,
+            _ => return Err(invalid("not a P3 or P6 PPM file")),
+        };
+        let mut tokens = Tokens {
+            bytes: &bytes,
+            pos: 2,
+        };
+        let width = tokens.next_number()?;
+        let height = tokens.next_number()?;
+        let max_value = tokens.next_number()?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid("invalid PPM maximum value"));
+        }
+
+        let count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or_else(|| invalid("PPM image too large"))?;
+        let values: Vec<usize> = if magic == 3 {
+            (0..count)
+                .map(|_| tokens.next_number())
+                .collect::<io::Result<_>>()?
+        } else {
+            // a single whitespace character separates the header from the raster
+            let start = tokens.pos + 1;
+            let size = if max_value < 256 { 1 } else { 2 };
+            let end = count
+                .checked_mul(size)
+                .and_then(|size| size.checked_add(start))
+                .ok_or_else(|| invalid("PPM image too large"))?;
+            let raster = bytes
+                .get(start..end)
+                .ok_or_else(|| invalid("truncated PPM raster"))?;
+            raster
+                .chunks(size)
+                .map(|c| c.iter().fold(0, |acc, &b| (acc << 8) | b as usize))
+                .collect()
+        };
+        let scale = 1. / max_value as f32;
+        let pixels = values
+            .chunks(3)
+            .map(|c| Colour::new(c[0] as f32, c[1] as f32, c[2] as f32) * scale)
+            .collect();
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+    /// Writes a binary (P6) PPM file with 8 bits per channel. Values are clamped to [0, 1] and
+    /// stored without any gamma encoding.
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for pixel in &self.pixels {
+            for c in 0..3 {
+                bytes.push((pixel[c].clamp(0., 1.) * 255. + 0.5) as u8);
+            }
+        }
+        fs::write(path, bytes)
+    }
+    /// Writes a PFM file with 32-bit floating point channels, keeping values as they are.
+    pub fn write_pfm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        // a negative scale marks little endian values, and rows are stored from the bottom
+        let mut bytes = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        for row in self.pixels.chunks(self.width.max(1)).rev() {
+            for pixel in row {
+                for c in 0..3 {
+                    bytes.extend_from_slice(&pixel[c].to_le_bytes());
+                }
+            }
+        }
+        fs::write(path, bytes)
+    }
+    /// Nearest pixel lookup, wrapping around at the edges. (0, 0) is the bottom left corner.
+    pub fn lookup(&self, u: f32, v: f32) -> Colour {
+        if self.pixels.is_empty() {
+            return Colour::new(0., 1., 1.);
+        }
+        let u = u - u.floor();
+        let v = v - v.floor();
+        let i = ((u * self.width as f32) as usize).min(self.width - 1);
+        let j = (((1. - v) * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+/// Whitespace separated tokens of a PPM file, where comments run from '#' to the end of the line.
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next_number(&mut self) -> io::Result<usize> {
+        let bytes = self.bytes;
+        loop {
+            while self.pos < bytes.len() && bytes[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            if self.pos < bytes.len() && bytes[self.pos] == b'#' {
+                while self.pos < bytes.len() && bytes[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = self.pos;
+        while self.pos < bytes.len() && !bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&bytes[start..self.pos])
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed PPM file"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str, bytes: &[u8]) -> io::Result<Image> {
+        let path = std::env::temp_dir().join(format!("traycer-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let image = Image::read_ppm(&path);
+        fs::remove_file(&path).unwrap();
+        image
+    }
+
+    #[test]
+    fn reads_plain_and_binary_ppm() {
+        let plain = read("plain.ppm", b"P3\n# comment\n2 1\n255\n255 0 0  0 51 255\n").unwrap();
+        let binary = read("binary.ppm", b"P6 2 1 255\n\xff\x00\x00\x00\x33\xff").unwrap();
+        for image in [plain, binary] {
+            assert_eq!((image.width, image.height), (2, 1));
+            assert_eq!(image.pixels[0], Colour::new(1., 0., 0.));
+            assert_eq!(image.pixels[1], Colour::new(0., 0.2, 1.));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_ppm() {
+        for (bytes, error) in [
+            (&b"P5 1 1 255\n\x00"[..], "not a P3 or P6 PPM file"),
+            (b"P6 1 1 0\n\x00\x00\x00", "invalid PPM maximum value"),
+            (b"P6 2 1 255\n\x00\x00\x00", "truncated PPM raster"),
+            (b"P3 1 1 255\n0 0", "malformed PPM file"),
+            (
+                b"P6 18446744073709551615 2 255\n\x00",
+                "PPM image too large",
+            ),
+            (b"P6 4294967296 4294967296 255\n\x00", "PPM image too large"),
+        ] {
+            let err = read("bad.ppm", bytes).err().unwrap();
+            assert_eq!(err.to_string(), error);
+        }
+    }
+}
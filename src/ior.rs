@@ -0,0 +1,109 @@
+/// Wavelength (in nm) of the helium d line, at which refractive indices are usually quoted and
+/// which is used when rendering in RGB.
+pub const D_LINE: f32 = 587.6;
+const F_LINE: f32 = 486.1;
+const C_LINE: f32 = 656.3;
+
+/// Refractive index of a dielectric, possibly depending on the wavelength. Dispersive indices
+/// split white light into its colours when rendering spectrally.
+#[derive(Debug, Clone, Copy)]
+pub enum Ior {
+    Constant(f32),
+    /// `a + b / λ²`, with the wavelength in micrometres.
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`, with the wavelength in micrometres.
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+    /// Index `nd` at the d line, with the dispersion given by the Abbe number `vd`. Lower
+    /// numbers disperse more, typical glasses ranging from 20 to 90.
+    Abbe {
+        nd: f32,
+        vd: f32,
+    },
+}
+
+impl Ior {
+    /// Borosilicate crown glass, the most common optical glass.
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.039_612, 0.231_792_3, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+    pub const DIAMOND: Ior = Ior::Sellmeier {
+        b: [0.3306, 4.3356, 0.],
+        c: [0.030_625, 0.011_236, 0.],
+    };
+
+    /// Index at the given wavelength in nm.
+    pub fn at(&self, lambda: f32) -> f32 {
+        let l2 = (lambda / 1000.).powi(2);
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).sqrt()
+            }
+            Ior::Abbe { nd, vd } => {
+                // Cauchy equation with the same index at the d line and the same difference
+                // between the F and C lines
+                let inv2 = |lambda: f32| (1000. / lambda).powi(2);
+                let b = (nd - 1.) / vd / (inv2(F_LINE) - inv2(C_LINE));
+                nd + b * (1. / l2 - inv2(D_LINE))
+            }
+        }
+    }
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+impl From<f32> for Ior {
+    fn from(n: f32) -> Self {
+        Ior::Constant(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bk7_matches_its_catalogue_index() {
+        assert!((Ior::BK7.at(D_LINE) - 1.5168).abs() < 1e-4);
+    }
+
+    #[test]
+    fn abbe_gives_the_stated_index_and_dispersion() {
+        let ior = Ior::Abbe { nd: 1.62, vd: 36. };
+        let n = |lambda| ior.at(lambda);
+        assert!((n(D_LINE) - 1.62).abs() < 1e-5);
+        assert!(((n(D_LINE) - 1.) / (n(F_LINE) - n(C_LINE)) - 36.).abs() < 0.05);
+    }
+
+    #[test]
+    fn index_decreases_with_wavelength() {
+        for ior in [
+            Ior::BK7,
+            Ior::DIAMOND,
+            Ior::Abbe { nd: 1.5, vd: 40. },
+            Ior::Cauchy { a: 1.5, b: 0.004 },
+        ] {
+            assert!(ior.is_dispersive());
+            let mut lambda = 380.;
+            while lambda < 780. {
+                assert!(
+                    ior.at(lambda + 10.) < ior.at(lambda),
+                    "{:?} {}",
+                    ior,
+                    lambda
+                );
+                lambda += 10.;
+            }
+        }
+        assert_eq!(Ior::Constant(1.3).at(400.), Ior::Constant(1.3).at(700.));
+    }
+}
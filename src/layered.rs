@@ -0,0 +1,287 @@
+use crate::bsdf::{Bsdf, BsdfSample, SurfaceBsdf};
+use crate::microfacet::{
+    fresnel_dielectric, ggx_d, ggx_g1, ggx_sample, reflect, thin_film_reflectance,
+};
+use crate::vec3d::{Colour, Vec3D};
+
+/// Wavelengths (in nm) at which interference is evaluated for the red, green and blue channels.
+pub const RGB_WAVELENGTHS: [f32; 3] = [650., 532., 450.];
+
+/// Clear lacquer coating a base material, like the varnish on wood or car paint.
+#[derive(Debug, Clone, Copy)]
+pub struct Coat {
+    pub refr_index: f32,
+    /// GGX roughness of the coat surface, zero for a perfectly smooth coat
+    pub roughness: f32,
+    pub thickness: f32,
+    /// absorption coefficient of the coat per unit length, tinting the light reaching the base
+    pub absorption: Colour,
+    pub thin_film: Option<ThinFilm>,
+}
+
+impl Coat {
+    pub fn new(refr_index: f32, roughness: f32) -> Coat {
+        Coat {
+            refr_index,
+            roughness,
+            thickness: 0.,
+            absorption: Colour::new(0., 0., 0.),
+            thin_film: None,
+        }
+    }
+}
+
+/// Film on top of the coat, whose interference colours the reflections. A soap bubble is a film
+/// with a coat of index 1, an oil slick one over a coat with the index of water.
+#[derive(Debug, Clone, Copy)]
+pub struct ThinFilm {
+    pub refr_index: f32,
+    /// thickness in nanometres, comparable to the wavelength of visible light
+    pub thickness: f32,
+}
+
+/// A `Coat` over the BSDF of the base material. Light reflects off the coat or goes through it,
+/// interacts with the base and is partially absorbed on the way in and out.
+pub struct LayeredBsdf {
+    pub base: Box<SurfaceBsdf>,
+    pub coat: Coat,
+    /// wavelengths (in nm) of the three channels of colours, at which interference in the thin
+    /// film is evaluated: `RGB_WAVELENGTHS`, or those carried by the path in spectral mode
+    pub wavelengths: [f32; 3],
+}
+
+impl LayeredBsdf {
+    fn fresnel(&self, cos: f32) -> Colour {
+        match self.coat.thin_film {
+            Some(film) => {
+                let r = |wavelength| {
+                    thin_film_reflectance(
+                        cos,
+                        film.refr_index,
+                        film.thickness,
+                        self.coat.refr_index,
+                        wavelength,
+                    )
+                };
+                Colour::new(
+                    r(self.wavelengths[0]),
+                    r(self.wavelengths[1]),
+                    r(self.wavelengths[2]),
+                )
+            }
+            None => {
+                let f = fresnel_dielectric(cos, self.coat.refr_index);
+                Colour::new(f, f, f)
+            }
+        }
+    }
+    fn alpha(&self) -> f32 {
+        (self.coat.roughness * self.coat.roughness).max(0.001)
+    }
+    fn is_smooth(&self) -> bool {
+        self.coat.roughness == 0.
+    }
+    /// Probability of sampling the coat rather than the base.
+    fn coat_probability(&self, wo: &Vec3D) -> f32 {
+        let f = self.fresnel(wo.z());
+        ((f.x() + f.y() + f.z()) / 3.).clamp(0.05, 0.95)
+    }
+    /// Fraction of the light going through the coat to the base and back out.
+    fn transmittance(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        let cos_o = wo.z().abs();
+        let cos_i = wi.z().abs();
+        let white = Colour::new(1., 1., 1.);
+        // distance travelled inside the coat, following the refracted directions
+        let refracted_cos = |cos: f32| {
+            (1. - (1. - cos * cos) / (self.coat.refr_index * self.coat.refr_index))
+                .max(1e-4)
+                .sqrt()
+        };
+        let distance =
+            self.coat.thickness * (1. / refracted_cos(cos_o) + 1. / refracted_cos(cos_i));
+        let absorbed = Colour::new(
+            (-self.coat.absorption.x() * distance).exp(),
+            (-self.coat.absorption.y() * distance).exp(),
+            (-self.coat.absorption.z() * distance).exp(),
+        );
+        (white - self.fresnel(cos_o)) * (white - self.fresnel(cos_i)) * absorbed
+    }
+    fn eval_coat(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        if self.is_smooth() || wi.z() <= 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        let alpha = self.alpha();
+        let h = (wo + wi).unit_vector();
+        self.fresnel(wo.dot(&h))
+            * ggx_d(&h, alpha, alpha)
+            * ggx_g1(wo, alpha, alpha)
+            * ggx_g1(wi, alpha, alpha)
+            / (4. * wo.z() * wi.z())
+    }
+    fn pdf_coat(&self, wo: &Vec3D, wi: &Vec3D) -> f32 {
+        if self.is_smooth() || wi.z() <= 0. {
+            return 0.;
+        }
+        let alpha = self.alpha();
+        let h = (wo + wi).unit_vector();
+        ggx_d(&h, alpha, alpha) * h.z() / (4. * wo.dot(&h))
+    }
+}
+
+impl Bsdf for LayeredBsdf {
+    fn sample(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let p_coat = self.coat_probability(wo);
+        if uc < p_coat {
+            if self.is_smooth() {
+                let wi = Vec3D::new(-wo.x(), -wo.y(), wo.z());
+                return Some(BsdfSample {
+                    wi,
+                    f: self.fresnel(wo.z()) / wi.z(),
+                    pdf: p_coat,
+                    specular: true,
+                    diffuse: false,
+                });
+            }
+            let alpha = self.alpha();
+            let wi = reflect(wo, &ggx_sample(alpha, alpha, u.0, u.1));
+            if wi.z() <= 0. {
+                return None;
+            }
+            return Some(BsdfSample {
+                wi,
+                f: self.eval(wo, &wi),
+                pdf: self.pdf(wo, &wi),
+                specular: false,
+                diffuse: false,
+            });
+        }
+
+        let base = self.base.sample(wo, (uc - p_coat) / (1. - p_coat), u)?;
+        if base.specular {
+            return Some(BsdfSample {
+                wi: base.wi,
+                f: base.f * self.transmittance(wo, &base.wi),
+                pdf: (1. - p_coat) * base.pdf,
+                specular: true,
+                diffuse: base.diffuse,
+            });
+        }
+        Some(BsdfSample {
+            wi: base.wi,
+            f: self.eval(wo, &base.wi),
+            pdf: self.pdf(wo, &base.wi),
+            specular: false,
+            diffuse: base.diffuse,
+        })
+    }
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        self.eval_coat(wo, wi) + self.transmittance(wo, wi) * self.base.eval(wo, wi)
+    }
+    fn pdf(&self, wo: &Vec3D, wi: &Vec3D) -> f32 {
+        let p_coat = self.coat_probability(wo);
+        p_coat * self.pdf_coat(wo, wi) + (1. - p_coat) * self.base.pdf(wo, wi)
+    }
+    fn is_specular(&self) -> bool {
+        self.is_smooth() && self.base.is_specular()
+    }
+    fn regularize(&mut self, roughness: f32) {
+        self.coat.roughness = self.coat.roughness.max(roughness);
+        self.base.regularize(roughness);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::tests::{check_bsdf, outgoing_directions};
+    use crate::bsdf::{LambertianBsdf, MetalBsdf};
+    use crate::sampler::seeded_rng;
+
+    fn white() -> Box<SurfaceBsdf> {
+        Box::new(SurfaceBsdf::Lambertian(LambertianBsdf {
+            albedo: Colour::new(1., 1., 1.),
+        }))
+    }
+
+    #[test]
+    fn rough_coats_are_consistent() {
+        let coats = [
+            Coat::new(1.5, 0.3),
+            Coat {
+                thickness: 0.5,
+                absorption: Colour::new(0.1, 0.5, 1.),
+                ..Coat::new(1.5, 0.5)
+            },
+            Coat {
+                thin_film: Some(ThinFilm {
+                    refr_index: 1.33,
+                    thickness: 400.,
+                }),
+                ..Coat::new(1.5, 0.3)
+            },
+        ];
+        for coat in coats {
+            let bsdf = LayeredBsdf {
+                base: white(),
+                coat,
+                wavelengths: RGB_WAVELENGTHS,
+            };
+            for wo in outgoing_directions() {
+                check_bsdf(&bsdf, &wo);
+            }
+        }
+        let metal = Box::new(SurfaceBsdf::Metal(MetalBsdf {
+            albedo: Colour::new(1., 1., 1.),
+            fuzziness: 0.6,
+        }));
+        let bsdf = LayeredBsdf {
+            base: metal,
+            coat: Coat::new(1.5, 0.4),
+            wavelengths: RGB_WAVELENGTHS,
+        };
+        for wo in outgoing_directions() {
+            check_bsdf(&bsdf, &wo);
+        }
+    }
+
+    #[test]
+    fn smooth_coat_conserves_energy() {
+        let bsdf = LayeredBsdf {
+            base: white(),
+            coat: Coat::new(1.5, 0.),
+            wavelengths: RGB_WAVELENGTHS,
+        };
+        let rng = seeded_rng(7);
+        for wo in outgoing_directions() {
+            let mut albedo = Colour::new(0., 0., 0.);
+            let samples = 100_000;
+            for _ in 0..samples {
+                if let Some(sample) = bsdf.sample(&wo, rng.f32(), (rng.f32(), rng.f32())) {
+                    albedo += sample.f * sample.wi.z().abs() / sample.pdf;
+                }
+            }
+            let albedo = albedo / samples as f32;
+            assert!(albedo.x() <= 1.005, "{:?}", albedo);
+        }
+    }
+
+    #[test]
+    fn thin_film_follows_the_wavelengths_of_the_path() {
+        let coat = Coat {
+            thin_film: Some(ThinFilm {
+                refr_index: 1.33,
+                thickness: 400.,
+            }),
+            ..Coat::new(1.5, 0.)
+        };
+        let bsdf = |wavelengths| LayeredBsdf {
+            base: white(),
+            coat,
+            wavelengths,
+        };
+        let rgb = bsdf(RGB_WAVELENGTHS).fresnel(0.8);
+        let spectral = bsdf([532., 450., 650.]).fresnel(0.8);
+        assert_eq!(spectral, Colour::new(rgb.y(), rgb.z(), rgb.x()));
+        assert_ne!(spectral, rgb);
+    }
+}
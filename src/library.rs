@@ -0,0 +1,84 @@
+use crate::ior::Ior;
+use crate::layered::Coat;
+use crate::material::Material;
+use crate::vec3d::Colour;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Materials of a scene, stored once and shared by name between the objects using them.
+#[derive(Debug, Default)]
+pub struct MaterialLibrary {
+    materials: HashMap<String, Arc<Material>>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> MaterialLibrary {
+        MaterialLibrary::default()
+    }
+    /// Library holding the common materials `glass`, `water`, `diamond`, `gold`, `plastic` and
+    /// `rubber`.
+    pub fn presets() -> MaterialLibrary {
+        let mut library = MaterialLibrary::new();
+        library.add(
+            "glass",
+            Material::Dielectric {
+                refr_index: Ior::BK7,
+                medium: None,
+            },
+        );
+        library.add(
+            "water",
+            Material::Dielectric {
+                refr_index: Ior::Abbe {
+                    nd: 1.333,
+                    vd: 55.7,
+                },
+                medium: None,
+            },
+        );
+        library.add(
+            "diamond",
+            Material::Dielectric {
+                refr_index: Ior::DIAMOND,
+                medium: None,
+            },
+        );
+        library.add(
+            "gold",
+            Material::Metal {
+                albedo: Colour::new(1., 0.766, 0.336),
+                fuzziness: 0.,
+            },
+        );
+        library.add(
+            "plastic",
+            Material::Layered {
+                base: Arc::new(Material::Lambertian {
+                    albedo: Colour::new(0.8, 0.8, 0.8),
+                }),
+                coat: Coat::new(1.5, 0.),
+            },
+        );
+        library.add(
+            "rubber",
+            Material::Layered {
+                base: Arc::new(Material::Lambertian {
+                    albedo: Colour::new(0.05, 0.05, 0.05),
+                }),
+                coat: Coat::new(1.5, 0.6),
+            },
+        );
+        library
+    }
+    /// Adds a material under `name`, replacing any previous one, and returns the shared handle
+    /// to it.
+    pub fn add(&mut self, name: &str, material: Material) -> Arc<Material> {
+        let material = Arc::new(material);
+        self.materials
+            .insert(name.to_string(), Arc::clone(&material));
+        material
+    }
+    pub fn get(&self, name: &str) -> Option<Arc<Material>> {
+        self.materials.get(name).cloned()
+    }
+}
@@ -1,48 +1,279 @@
-#![feature(clamp)]
+mod aov;
+mod bsdf;
 mod camera;
+mod checkpoint;
 mod colour;
+mod denoise;
+mod emission;
+mod film;
+mod filter;
 mod hittable;
+mod image;
+mod ior;
+mod layered;
+mod library;
 mod material;
+mod medium;
+mod microfacet;
+mod normal_map;
+mod onb;
+mod options;
+mod principled;
 mod ray;
+mod sampler;
+mod scene;
+mod spectrum;
+mod stats;
+mod texture;
+mod tile;
 mod utils;
 mod vec3d;
+use crate::aov::{Aov, AovBuffers};
 use crate::camera::Camera;
+use crate::checkpoint::Checkpoint;
 use crate::colour::get_colour;
-use crate::hittable::{HitRecord, Hittable, HittableList, Sphere};
-use crate::material::Material;
+use crate::denoise::{Denoiser, Features};
+use crate::film::Film;
+use crate::hittable::{HitRecord, Hittable};
+use crate::image::Image;
+use crate::material::Lobe;
+use crate::medium::MediumEvent;
+use crate::options::Options;
 use crate::ray::Ray;
+use crate::sampler::{sample_key, sample_sphere, Sampler};
+use crate::scene::Scene;
+use crate::spectrum::{rgb_to_xyz, ColourMatrix, Wavelengths};
+use crate::stats::{Progress, RenderStats};
+use crate::tile::tiles;
+use crate::utils::RunningStats;
 use crate::vec3d::{Colour, Point3D, Vec3D};
 use rayon::prelude::*;
-use std::f32::consts;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-fn ray_colour(r: &Ray, world: &dyn Hittable, depth: u16) -> Colour {
-    if depth <= 0 {
-        return Colour::new(0., 0., 0.);
-    }
+/// Settings of the paths traced from the camera.
+struct PathSettings {
+    /// largest number of rays in a path, the camera ray included
+    max_depth: u16,
+    /// largest number of scattering events of each kind in a path
+    max_diffuse: u16,
+    max_glossy: u16,
+    max_transmission: u16,
+    max_volume: u16,
+    /// number of bounces after which paths are terminated at random, if any
+    roulette_depth: Option<u16>,
+    /// largest value of the radiance seen directly by the camera
+    clamp_direct: Option<f32>,
+    /// largest value of the radiance reaching the camera after a bounce
+    clamp_indirect: Option<f32>,
+    /// roughness lobes are widened to once the path has bounced off a surface that is not
+    /// perfectly smooth, see `Bsdf::regularize`
+    regularize: Option<f32>,
+}
+
+/// Number of scattering events of each kind a path has gone through.
+#[derive(Default)]
+struct Bounces {
+    diffuse: u16,
+    glossy: u16,
+    transmission: u16,
+    volume: u16,
+}
+
+/// Radiance reaching the origin of the camera ray `r`, following the path it starts by
+/// scattering at each hit until it leaves the scene or is terminated.
+///
+/// `throughput` is the fraction of the light at the end of the path that reaches the camera.
+/// Once the path has bounced `roulette_depth` times, it is terminated before each ray with a
+/// probability that grows as its throughput falls, the surviving paths being weighted up to
+/// make up for those terminated.
+fn ray_colour(
+    r: &Ray,
+    world: &dyn Hittable,
+    sampler: &mut dyn Sampler,
+    settings: &PathSettings,
+) -> Colour {
+    // the camera sees light from its first hit directly, and the rest indirectly, each being
+    // clamped on its own
+    let mut direct = Colour::new(0., 0., 0.);
+    let mut indirect = Colour::new(0., 0., 0.);
+    let mut throughput = Colour::new(1., 1., 1.);
+    let mut bounces = Bounces::default();
+    // whether the path has bounced off a surface that is not perfectly smooth
+    let mut rough = false;
+    let mut r = *r;
 
-    let mut rec = HitRecord::default();
-    if let Some(material) = world.hit(r, f32::EPSILON, f32::INFINITY, &mut rec) {
-        if let Some((scattered, attenuation)) = material.scatter(r, rec) {
-            return attenuation * ray_colour(&scattered, world, depth - 1);
+    for depth in 0..settings.max_depth {
+        if settings.roulette_depth.is_some_and(|min| depth >= min) {
+            let survival = throughput
+                .x()
+                .max(throughput.y())
+                .max(throughput.z())
+                .min(0.95);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+        let radiance = if depth == 0 {
+            &mut direct
         } else {
-            return Colour::new(0., 0., 0.);
+            &mut indirect
+        };
+
+        stats::count_ray();
+        let mut rec = HitRecord::default();
+        let hit = world.hit(&r, f32::EPSILON, f32::INFINITY, &mut rec);
+
+        // light travelling through a medium is absorbed, and may be scattered before reaching the
+        // next surface
+        if let Some(medium) = r.medium() {
+            let length = r.direction().length();
+            let max_distance = if hit { rec.t() * length } else { f32::INFINITY };
+            match medium.sample(max_distance, sampler.get_2d()) {
+                MediumEvent::Scatter { distance, weight } => {
+                    bounces.volume += 1;
+                    if bounces.volume > settings.max_volume {
+                        break;
+                    }
+                    throughput *= reflectance(&r, &weight);
+                    r = r.scattered(r.at(distance / length), sample_sphere(sampler.get_2d()));
+                    rough = true;
+                    continue;
+                }
+                MediumEvent::Pass { weight } => throughput *= reflectance(&r, &weight),
+            }
+        }
+
+        let Some(material) = rec.material().cloned() else {
+            *radiance += throughput * sky(&r);
+            break;
+        };
+        *radiance += throughput * material.emitted(&r, &rec);
+        let regularize = settings.regularize.filter(|_| rough);
+        let Some(scattered) = material.scatter(&r, rec, sampler, regularize) else {
+            break;
+        };
+        let (count, max) = match scattered.lobe {
+            Lobe::Diffuse => (&mut bounces.diffuse, settings.max_diffuse),
+            Lobe::Glossy => (&mut bounces.glossy, settings.max_glossy),
+            Lobe::Transmission => (&mut bounces.transmission, settings.max_transmission),
+        };
+        *count += 1;
+        if *count > max {
+            break;
         }
+        rough = rough || !scattered.specular;
+        throughput *= reflectance(&r, &scattered.attenuation);
+        r = scattered.ray;
     }
+    clamp_radiance(direct, settings.clamp_direct)
+        + clamp_radiance(indirect, settings.clamp_indirect)
+}
+
+/// Radiance of the sky seen by `r`.
+fn sky(r: &Ray) -> Colour {
     let unit_dir = r.direction().unit_vector();
     let t = 0.5 * (unit_dir.y() + 1.);
     let white = Colour::new(1., 1., 1.);
     let blue = Colour::new(0.5, 0.7, 1.);
-    (1. - t) * white + t * blue
+    let sky = (1. - t) * white + t * blue;
+    match r.wavelengths() {
+        Some(wavelengths) => wavelengths.illuminant(&sky),
+        None => sky,
+    }
+}
+
+/// Scales `radiance` down so that none of its components exceeds `max`, keeping its hue.
+fn clamp_radiance(radiance: Colour, max: Option<f32>) -> Colour {
+    match max {
+        Some(max) => {
+            let largest = radiance.x().max(radiance.y()).max(radiance.z());
+            if largest > max {
+                radiance * (max / largest)
+            } else {
+                radiance
+            }
+        }
+        None => radiance,
+    }
+}
+
+/// Converts an RGB attenuation to the wavelengths carried by the ray, if any.
+fn reflectance(r: &Ray, rgb: &Colour) -> Colour {
+    match r.wavelengths() {
+        Some(wavelengths) => wavelengths.reflectance(rgb),
+        None => *rgb,
+    }
+}
+
+/// Writes the `width` x `height` image `pixels`, laid out like the film, as a plain PPM.
+fn write_ppm(
+    (width, height): (usize, usize),
+    pixels: &[Colour],
+    white_balance: &ColourMatrix,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(out, "P3\n{} {}\n255\n", width, height)?;
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            out.write_all(get_colour(white_balance.apply(pixel), 1.).as_bytes())?;
+        }
+    }
+    out.flush()
+}
+
+/// Writes the image to `path` through a temporary file, so that the file is always complete for
+/// whoever reads it while the render goes on.
+fn write_image(
+    size: (usize, usize),
+    pixels: &[Colour],
+    white_balance: &ColourMatrix,
+    path: &Path,
+) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    write_ppm(size, pixels, white_balance, &mut File::create(&temporary)?)?;
+    fs::rename(&temporary, path)
+}
+
+/// Samples per pixel every pixel has at the end of each of the passes taking the render from
+/// `samples_taken` to `samples_per_pixel`, `pass_samples` at a time, the last pass taking what is
+/// left.
+fn pass_ends(samples_taken: u32, samples_per_pixel: u32, pass_samples: u32) -> Vec<u32> {
+    (samples_taken..samples_per_pixel)
+        .step_by(pass_samples as usize)
+        .map(|start| (start + pass_samples).min(samples_per_pixel))
+        .collect()
 }
 
 fn main() {
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
     let aspect_ratio: f32 = 16. / 9.;
     let image_width: usize = 1920;
     let image_height: usize = (image_width as f32 / aspect_ratio) as usize;
-    let samples_per_pixel: u16 = 240;
-    let max_depth: u16 = 36;
-
-    print!("P3\n{} {}\n255\n", image_width, image_height);
+    let image_size = (image_width, image_height);
+    let samples_per_pixel = options.samples_per_pixel;
+    let settings = PathSettings {
+        max_depth: options.max_depth,
+        max_diffuse: options.diffuse_bounces.unwrap_or(u16::MAX),
+        max_glossy: options.glossy_bounces.unwrap_or(u16::MAX),
+        max_transmission: options.transmission_bounces.unwrap_or(u16::MAX),
+        max_volume: options.volume_bounces.unwrap_or(u16::MAX),
+        roulette_depth: options.roulette_depth,
+        clamp_direct: options.clamp_direct,
+        clamp_indirect: options.clamp_indirect,
+        regularize: options.regularize,
+    };
 
     let origin = Point3D::new(13., 2., 3.);
     let lookat = Point3D::new(0., 0., 0.);
@@ -58,36 +289,397 @@ fn main() {
         aspect_ratio,
         aperture,
         focus_dist,
+        0.,
+        1.,
     );
 
-    let world = HittableList::random_scene();
-
-    let pixels = (0..image_height)
-        .into_par_iter()
-        .rev()
-        .map(|h| {
-            (0..image_width)
-                .into_par_iter()
-                .map(|w| {
-                    let pixel_colour = (0..samples_per_pixel)
-                        .into_par_iter()
-                        .map(|_| {
-                            let u = (w as f32 + fastrand::f32()) / (image_width - 1) as f32;
-                            let v = (h as f32 + fastrand::f32()) / (image_height - 1) as f32;
-                            let r: Ray = cam.get_ray(u, v);
-                            ray_colour(&r, &world, max_depth)
-                        })
-                        .collect::<Vec<_>>()
-                        .iter()
-                        .fold(Colour::new(0., 0., 0.), |acc, x| acc + x);
-                    get_colour(pixel_colour, samples_per_pixel as f32)
-                })
-                .collect::<Vec<_>>()
-                .join("")
+    let scene = match &options.scene {
+        Some(path) => Scene::load(path).unwrap_or_else(|err| {
+            eprintln!("cannot load scene {}: {}", path.display(), err);
+            std::process::exit(1);
+        }),
+        None => Scene::random(options.seed),
+    };
+    let world = &scene.world;
+    let white_balance = options
+        .white_balance
+        .or(scene.white_balance)
+        .map_or(ColourMatrix::identity(), ColourMatrix::white_balance);
+
+    let film = Film::new(image_width, image_height, options.filter);
+    let tiles = tiles(
+        image_width,
+        image_height,
+        options.tile_size,
+        options.tile_order,
+    );
+    // statistics of the samples taken in each pixel, kept across passes and grouped by tile so
+    // that each worker only locks those of its own tile
+    let mut pixel_stats = tiles
+        .iter()
+        .map(|tile| Mutex::new(vec![RunningStats::default(); tile.width() * tile.height()]))
+        .collect::<Vec<_>>();
+    let config = Checkpoint::config(&options, &scene, image_width, image_height);
+    let mut samples_taken = 0;
+    if let (true, Some(path)) = (options.resume, &options.checkpoint) {
+        let checkpoint = Checkpoint::read(path, &config, image_width * image_height)
+            .unwrap_or_else(|err| {
+                eprintln!("cannot resume from {}: {}", path.display(), err);
+                std::process::exit(1);
+            });
+        film.set_sums(&checkpoint.sums);
+        for (tile, stats) in tiles.iter().zip(&mut pixel_stats) {
+            for ((w, h), stats) in tile.pixels().zip(stats.get_mut().unwrap()) {
+                *stats = checkpoint.stats[h * image_width + w];
+            }
+        }
+        samples_taken = checkpoint.samples_taken;
+    }
+    let pass_samples = options
+        .pass_samples
+        .unwrap_or(samples_per_pixel)
+        .min(samples_per_pixel);
+    let pass_ends = pass_ends(samples_taken, samples_per_pixel, pass_samples);
+    let time_limit = options.time_limit.map(Duration::from_secs_f32);
+    let write_interval = options.write_interval.map(Duration::from_secs_f32);
+    let checkpoint_interval = Duration::from_secs_f32(options.checkpoint_interval);
+    let render_stats = Mutex::new(RenderStats::default());
+    let progress = Progress::new(tiles.len() * pass_ends.len(), time_limit);
+    let deadline = time_limit.map(|limit| Instant::now() + limit);
+    // the first interrupt lets the tiles under way finish, the second one exits right away
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        let registered =
+            signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&interrupted))
+                .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&interrupted)));
+        if let Err(err) = registered {
+            eprintln!("cannot handle signal {}: {}", signal, err);
+        }
+    }
+    // workers hold this for reading while they merge a tile into the film and store the
+    // statistics of its pixels, and snapshots of the render for writing, so that snapshots do not
+    // see half-merged tiles
+    let merging = RwLock::new(());
+    let last_write = Mutex::new(Instant::now());
+    let write_snapshot = |path: &Path| {
+        let pixels = {
+            let _guard = merging.write().unwrap();
+            film.pixels()
+        };
+        if let Err(err) = write_image(image_size, &pixels, &white_balance, path) {
+            eprintln!("\ncannot write {}: {}", path.display(), err);
+        }
+        *last_write.lock().unwrap() = Instant::now();
+    };
+    // samples per pixel every pixel has once the pass under way is done
+    let pass_start = AtomicU32::new(samples_taken);
+    let last_checkpoint = Mutex::new(Instant::now());
+    let write_checkpoint = |path: &Path| {
+        let guard = merging.write().unwrap();
+        let mut stats = vec![RunningStats::default(); image_width * image_height];
+        for (tile, tile_stats) in tiles.iter().zip(&pixel_stats) {
+            for ((w, h), tile_stats) in tile.pixels().zip(tile_stats.lock().unwrap().iter()) {
+                stats[h * image_width + w] = *tile_stats;
+            }
+        }
+        let checkpoint = Checkpoint {
+            config: config.clone(),
+            samples_taken: pass_start.load(Ordering::Relaxed),
+            sums: film.sums(),
+            stats,
+        };
+        drop(guard);
+        if let Err(err) = checkpoint.write(path) {
+            eprintln!("\ncannot write {}: {}", path.display(), err);
+        }
+        *last_checkpoint.lock().unwrap() = Instant::now();
+    };
+    // with adaptive sampling, pixels stop taking samples once the standard error of their
+    // luminance is small enough relative to it
+    let converged = |stats: &RunningStats| {
+        options.adaptive_threshold.is_some_and(|threshold| {
+            stats.count() >= options.min_samples_per_pixel && stats.relative_error(0.01) < threshold
         })
-        .collect::<Vec<_>>()
-        .join("");
+    };
+
+    for pass_end in pass_ends {
+        pass_start.store(samples_taken, Ordering::Relaxed);
+        let stopped = AtomicBool::new(false);
+        let unfinished = AtomicBool::new(false);
+        // workers take the tiles in order, each rendering a whole tile with a sampler and film of
+        // its own before merging it into the image
+        let next_tile = AtomicUsize::new(0);
+        (0..rayon::current_num_threads())
+            .into_par_iter()
+            .for_each(|_| {
+                let mut sampler = options
+                    .sampler
+                    .create(samples_per_pixel as usize, options.seed);
+                let sampler = &mut sampler;
+                loop {
+                    if interrupted.load(Ordering::Relaxed)
+                        || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                    {
+                        stopped.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    let Some(tile) = tiles.get(index) else {
+                        break;
+                    };
+                    let tile_film = film.for_tile(tile);
+                    let mut tile_stats = pixel_stats[index].lock().unwrap().clone();
+                    for ((w, h), stats) in tile.pixels().zip(tile_stats.iter_mut()) {
+                        while stats.count() < pass_end && !converged(stats) {
+                            sampler.start_pixel_sample((w, h), stats.count() as usize);
+                            let (du, dv) = sampler.get_2d();
+                            let r = cam
+                                .pixel_ray(
+                                    (w as f32 + du, h as f32 + dv),
+                                    (image_width, image_height),
+                                    sampler,
+                                )
+                                .with_key(sample_key(options.seed, (w, h), stats.count() as usize));
+                            stats::count_camera_ray();
+                            let radiance = if options.spectral {
+                                let wavelengths = Wavelengths::sample(sampler.get_1d());
+                                let r = r.with_wavelengths(Some(wavelengths));
+                                let radiance = ray_colour(&r, world, sampler, &settings);
+                                wavelengths.to_rgb(&radiance)
+                            } else {
+                                ray_colour(&r, world, sampler, &settings)
+                            };
+                            // samples that are not numbers are dropped for black ones, which
+                            // keeps the statistics of the pixel meaningful
+                            let radiance = if radiance.x().is_finite()
+                                && radiance.y().is_finite()
+                                && radiance.z().is_finite()
+                            {
+                                radiance
+                            } else {
+                                stats::count_invalid_sample();
+                                Colour::new(0., 0., 0.)
+                            };
+                            tile_film.add_sample(w as f32 + du, h as f32 + dv, &radiance);
+                            stats.add(rgb_to_xyz(&radiance).y());
+                        }
+                        if stats.count() < samples_per_pixel && !converged(stats) {
+                            unfinished.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    {
+                        let _guard = merging.read().unwrap();
+                        film.merge(&tile_film);
+                        *pixel_stats[index].lock().unwrap() = tile_stats;
+                    }
+                    progress.tile_done();
+                    if let (Some(path), Some(interval)) = (&options.output, write_interval) {
+                        if last_write.lock().unwrap().elapsed() >= interval {
+                            write_snapshot(path);
+                        }
+                    }
+                    if let Some(path) = &options.checkpoint {
+                        if last_checkpoint.lock().unwrap().elapsed() >= checkpoint_interval {
+                            write_checkpoint(path);
+                        }
+                    }
+                }
+                *render_stats.lock().unwrap() += stats::take_local();
+            });
+        if stopped.into_inner() {
+            break;
+        }
+        samples_taken = pass_end;
+        if !unfinished.into_inner() {
+            break;
+        }
+        if samples_taken < samples_per_pixel && write_interval.is_none() {
+            if let Some(path) = &options.output {
+                write_snapshot(path);
+            }
+        }
+    }
+    pass_start.store(samples_taken, Ordering::Relaxed);
+    if let Some(path) = &options.checkpoint {
+        write_checkpoint(path);
+    }
+    let elapsed = progress.elapsed();
+    let mut sample_counts = vec![0; image_width * image_height];
+    // variance of the estimate of the luminance of each pixel
+    let mut pixel_variance = vec![0.; image_width * image_height];
+    for (tile, stats) in tiles.iter().zip(pixel_stats) {
+        for ((w, h), stats) in tile.pixels().zip(stats.into_inner().unwrap()) {
+            sample_counts[h * image_width + w] = stats.count();
+            pixel_variance[h * image_width + w] = stats.variance() / stats.count().max(1) as f32;
+        }
+    }
+    if interrupted.load(Ordering::Relaxed) {
+        eprintln!("\nInterrupted, writing the image rendered so far.");
+    }
+
+    if let Some(path) = &options.sample_count_image {
+        let pixels = (0..image_height)
+            .rev()
+            .flat_map(|h| (0..image_width).map(move |w| (w, h)))
+            .map(|(w, h)| {
+                let fraction = sample_counts[h * image_width + w] as f32 / samples_per_pixel as f32;
+                Colour::new(fraction, fraction, fraction)
+            })
+            .collect();
+        if let Err(err) = Image::new(image_width, image_height, pixels).write_ppm(path) {
+            eprintln!("cannot write {}: {}", path.display(), err);
+        }
+    }
+
+    // the denoiser is guided by output variables, which are rendered along with those asked for
+    let mut aovs = options.aovs.iter().map(|(aov, _)| *aov).collect::<Vec<_>>();
+    if options.denoise {
+        for aov in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+    }
+    let aov_buffers = (!aovs.is_empty()).then(|| {
+        AovBuffers::render(
+            &aovs,
+            &cam,
+            world,
+            options.sampler,
+            samples_per_pixel,
+            options.seed,
+            options.aov_samples.clamp(1, samples_per_pixel.max(1)),
+            image_size,
+        )
+    });
+    if let Some(buffers) = &aov_buffers {
+        for (aov, path) in &options.aovs {
+            if let Some(Err(err)) = buffers.image(*aov).map(|image| image.write_pfm(path)) {
+                eprintln!("\ncannot write {}: {}", path.display(), err);
+            }
+        }
+    }
 
-    print!("{}", pixels);
+    let mut pixels = film.pixels();
+    if let (true, Some(buffers)) = (options.denoise, &aov_buffers) {
+        let feature = |aov| buffers.get(aov).unwrap_or_default();
+        let depth = feature(Aov::Depth)
+            .iter()
+            .map(|depth| depth.x())
+            .collect::<Vec<_>>();
+        let features = Features {
+            albedo: feature(Aov::Albedo),
+            normal: feature(Aov::Normal),
+            depth: &depth,
+        };
+        let denoiser = Denoiser {
+            iterations: options.denoise_iterations,
+            strength: options.denoise_strength,
+        };
+        pixels = denoiser.denoise(image_size, &pixels, &pixel_variance, &features);
+    }
+    let written = match &options.output {
+        Some(path) => write_image(image_size, &pixels, &white_balance, path),
+        None => write_ppm(
+            image_size,
+            &pixels,
+            &white_balance,
+            &mut io::stdout().lock(),
+        ),
+    };
+    if let Err(err) = written {
+        eprintln!("\ncannot write the image: {}", err);
+    }
     eprintln!("\nDone.");
+    eprintln!("{}", render_stats.into_inner().unwrap().report(elapsed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{HittableList, Sphere};
+    use crate::material::Material;
+    use crate::sampler::SamplerKind;
+    use crate::texture::Texture;
+
+    fn settings(roulette_depth: Option<u16>) -> PathSettings {
+        PathSettings {
+            max_depth: 64,
+            max_diffuse: u16::MAX,
+            max_glossy: u16::MAX,
+            max_transmission: u16::MAX,
+            max_volume: u16::MAX,
+            roulette_depth,
+            clamp_direct: None,
+            clamp_indirect: None,
+            regularize: None,
+        }
+    }
+
+    /// Average radiance reaching the centre of a bright diffuse sphere letting some of the sky
+    /// through, where paths bounce many times.
+    fn average_radiance(settings: &PathSettings) -> Colour {
+        let shell = Material::Cutout {
+            base: Arc::new(Material::Lambertian {
+                albedo: Colour::new(0.9, 0.6, 0.3),
+            }),
+            opacity: Texture::from(0.8),
+        };
+        let world = HittableList::new(vec![Box::new(Sphere::new(
+            Point3D::new(0., 0., 0.),
+            1.,
+            Arc::new(shell),
+        ))]);
+        let mut sampler = SamplerKind::Independent.create(1, 1);
+        let paths = 200_000;
+        let mut total = Colour::new(0., 0., 0.);
+        for index in 0..paths {
+            sampler.start_pixel_sample((index, 0), 0);
+            let direction = sample_sphere(sampler.get_2d());
+            let r = Ray::new(Point3D::new(0., 0., 0.), direction, 0.).with_key(sample_key(
+                1,
+                (index, 0),
+                0,
+            ));
+            total += ray_colour(&r, &world, &mut sampler, settings);
+        }
+        total / paths as f32
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let expected = average_radiance(&settings(None));
+        for roulette_depth in [0, 1, 3] {
+            let radiance = average_radiance(&settings(Some(roulette_depth)));
+            for c in 0..3 {
+                assert!(
+                    (radiance[c] - expected[c]).abs() < 0.01 * expected[c],
+                    "{:?} {:?}",
+                    radiance,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clamping_keeps_the_hue() {
+        let radiance = Colour::new(8., 4., 2.);
+        assert_eq!(clamp_radiance(radiance, None), radiance);
+        assert_eq!(clamp_radiance(radiance, Some(10.)), radiance);
+        let clamped = clamp_radiance(radiance, Some(2.));
+        assert!((clamped - Colour::new(2., 1., 0.5)).length() < 1e-6);
+    }
+
+    #[test]
+    fn passes_take_what_is_left_at_the_end() {
+        assert_eq!(pass_ends(0, 10, 4), [4, 8, 10]);
+        assert_eq!(pass_ends(0, 12, 4), [4, 8, 12]);
+        assert_eq!(pass_ends(0, 7, 7), [7]);
+        assert_eq!(pass_ends(0, 1, 1), [1]);
+        // resumed renders go on from where the checkpoint stopped
+        assert_eq!(pass_ends(5, 10, 4), [9, 10]);
+        assert!(pass_ends(10, 10, 4).is_empty());
+        assert!(pass_ends(12, 10, 4).is_empty());
+    }
 }
@@ -1,62 +1,458 @@
+use crate::bsdf::{Bsdf, DielectricBsdf, LambertianBsdf, MetalBsdf, OrenNayarBsdf, SurfaceBsdf};
+use crate::emission::Emission;
 use crate::hittable::HitRecord;
+use crate::ior::{Ior, D_LINE};
+use crate::layered::{Coat, LayeredBsdf, RGB_WAVELENGTHS};
+use crate::medium::Medium;
+use crate::normal_map::NormalMap;
+use crate::onb::Onb;
+use crate::principled::{Principled, PrincipledBsdf};
 use crate::ray::Ray;
+use crate::sampler::{hash, hash_float, Sampler};
+use crate::texture::Texture;
 use crate::vec3d::{Colour, Vec3D};
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Copy)]
+/// Ray leaving a surface, with the weight of the light it brings back.
+pub struct Scattered {
+    pub ray: Ray,
+    pub attenuation: Colour,
+    /// the direction was sampled from a delta lobe
+    pub specular: bool,
+    pub lobe: Lobe,
+}
+
+/// Kind of scattering off a surface, paths being limited in how many times they scatter in
+/// each way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lobe {
+    Diffuse,
+    /// reflection off a glossy or mirror-like lobe
+    Glossy,
+    /// refraction through the surface
+    Transmission,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
 pub enum Material {
-    Lambertian { albedo: Colour },
-    Metal { albedo: Colour, fuzziness: f32 },
-    Dielectric { refr_index: f32 },
+    Lambertian {
+        albedo: Colour,
+    },
+    /// Rough diffuse surface, `sigma` being the roughness in radians. Reduces to `Lambertian`
+    /// when it is zero.
+    OrenNayar {
+        albedo: Colour,
+        sigma: f32,
+    },
+    Metal {
+        albedo: Colour,
+        fuzziness: f32,
+    },
+    /// `medium` is what fills the inside of the object, `None` for clear glass.
+    Dielectric {
+        refr_index: Ior,
+        medium: Option<Medium>,
+    },
+    /// Translucent material like skin, wax or marble, where light enters through a smooth
+    /// dielectric boundary and scatters around inside the object before leaving it. See
+    /// `Medium::subsurface` for the meaning of the parameters.
+    Subsurface {
+        albedo: Colour,
+        mean_free_path: Colour,
+        refr_index: Ior,
+    },
+    Principled(Principled),
+    /// Surface giving off light without reflecting any.
+    DiffuseLight {
+        emission: Emission,
+    },
+    /// `base` under a clear coat, seen from the front face of the object.
+    Layered {
+        base: Arc<Material>,
+        coat: Coat,
+    },
+    /// `base` with its shading normal perturbed by `normal_map`. It should be the outermost
+    /// material when combined with others.
+    NormalMapped {
+        base: Arc<Material>,
+        normal_map: NormalMap,
+    },
+    /// `base` with parts of its surface cut out, like the outline of a leaf on a card. Surfaces
+    /// are hit with a probability given by the scalar value of `opacity`, and rays go through
+    /// them otherwise. This is decided in `Hittable::hit`, which every ray goes through, so
+    /// light reaching a point through a cutout is never blocked by it.
+    Cutout {
+        base: Arc<Material>,
+        opacity: Texture,
+    },
+    /// Blend of two materials, the scalar value of `mask` giving the fraction of `second`, like
+    /// rust patches on metal. Each hit sees one of them, picked at random with the probability
+    /// given by the mask, see `Material::mix_child`. The inside of objects is filled with the
+    /// medium of `first` if it has one, and otherwise that of `second`.
+    Mix {
+        first: Arc<Material>,
+        second: Arc<Material>,
+        mask: Texture,
+    },
 }
 
 impl Material {
-    pub fn scatter(&self, r_in: &Ray, rec: HitRecord) -> Option<(Ray, Colour)> {
+    /// Scattering function at the point where the ray `r` hits the surface, for light of
+    /// wavelength `lambda` (in nm), working in the shading frame around `rec.shading_normal()`.
+    pub fn bsdf(&self, r: &Ray, rec: &HitRecord, lambda: f32) -> SurfaceBsdf {
         match self {
             Material::Lambertian { albedo } => {
-                let scatter_direction: Vec3D = rec.normal() + Vec3D::random_unit_vector();
-                Some((Ray::new(rec.p(), scatter_direction), *albedo))
-            }
-            Material::Metal { albedo, fuzziness } => {
-                let reflected = r_in.direction().unit_vector().reflect(&rec.normal());
-                let scattered = Ray::new(
-                    rec.p(),
-                    reflected + *fuzziness * Vec3D::random_in_unit_sphere(),
-                );
-                if scattered.direction().dot(&rec.normal()) > 0. {
-                    return Some((scattered, *albedo));
-                } else {
-                    None
-                }
+                SurfaceBsdf::Lambertian(LambertianBsdf { albedo: *albedo })
+            }
+            Material::OrenNayar { albedo, sigma } => SurfaceBsdf::OrenNayar(OrenNayarBsdf {
+                albedo: *albedo,
+                sigma: *sigma,
+            }),
+            Material::Metal { albedo, fuzziness } => SurfaceBsdf::Metal(MetalBsdf {
+                albedo: *albedo,
+                fuzziness: *fuzziness,
+            }),
+            Material::Dielectric { refr_index, .. } | Material::Subsurface { refr_index, .. } => {
+                let n = refr_index.at(lambda);
+                SurfaceBsdf::Dielectric(DielectricBsdf {
+                    eta: if rec.front_face() { n } else { 1. / n },
+                    roughness: 0.,
+                })
+            }
+            Material::Principled(principled) => {
+                SurfaceBsdf::Principled(PrincipledBsdf::at(principled, rec))
             }
-            Material::Dielectric { refr_index } => {
-                let attenuation = Colour::new(1., 1., 1.);
-                let refr_index_ratio = if rec.front_face() {
-                    1. / refr_index
+            Material::DiffuseLight { .. } => SurfaceBsdf::Lambertian(LambertianBsdf {
+                albedo: Colour::new(0., 0., 0.),
+            }),
+            Material::Layered { base, coat } => {
+                if rec.front_face() {
+                    SurfaceBsdf::Layered(LayeredBsdf {
+                        base: Box::new(base.bsdf(r, rec, lambda)),
+                        coat: *coat,
+                        wavelengths: r
+                            .wavelengths()
+                            .map_or(RGB_WAVELENGTHS, |w| [w.lambda(0), w.lambda(1), w.lambda(2)]),
+                    })
                 } else {
-                    *refr_index
-                };
-                let unit_dir = r_in.direction().unit_vector();
-                assert!(unit_dir.dot(&rec.normal()) < 0.);
-
-                let cos_theta = -unit_dir.dot(&rec.normal());
-                let sin_theta = (1. - cos_theta * cos_theta).sqrt();
-                if refr_index_ratio * sin_theta > 1. {
-                    let reflected = unit_dir.reflect(&rec.normal());
-                    return Some((Ray::new(rec.p(), reflected), attenuation));
+                    base.bsdf(r, rec, lambda)
                 }
-                let reflect_prob = shlick(cos_theta, refr_index_ratio);
-                if fastrand::f32() < reflect_prob {
-                    let reflected = unit_dir.reflect(&rec.normal());
-                    return Some((Ray::new(rec.p(), reflected), attenuation));
-                }
-                let refracted = unit_dir.refract(&rec.normal(), refr_index_ratio);
-                Some((Ray::new(rec.p(), refracted), attenuation))
             }
+            Material::NormalMapped { base, .. } | Material::Cutout { base, .. } => {
+                base.bsdf(r, rec, lambda)
+            }
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let (child, r) = Material::mix_child(r, rec, first, second, mask);
+                child.bsdf(&r, rec, lambda)
+            }
+        }
+    }
+    /// Medium filling the inside of objects made of this material.
+    pub fn interior(&self) -> Option<Medium> {
+        match self {
+            Material::Dielectric { medium, .. } => *medium,
+            Material::Subsurface {
+                albedo,
+                mean_free_path,
+                ..
+            } => Some(Medium::subsurface(*albedo, *mean_free_path)),
+            Material::Layered { base, .. }
+            | Material::NormalMapped { base, .. }
+            | Material::Cutout { base, .. } => base.interior(),
+            Material::Mix { first, second, .. } => first.interior().or_else(|| second.interior()),
+            _ => None,
+        }
+    }
+    /// Whether the ray `r` reaching the surface at `rec` stops there rather than going through
+    /// it.
+    pub fn is_opaque_at(&self, r: &Ray, rec: &HitRecord) -> bool {
+        match self {
+            Material::Cutout { base, opacity } => {
+                let alpha = opacity.scalar(rec.u(), rec.v(), &rec.p());
+                (alpha >= 1. || Material::hit_float(r, rec, 0) < alpha) && base.is_opaque_at(r, rec)
+            }
+            Material::Layered { base, .. } | Material::NormalMapped { base, .. } => {
+                base.is_opaque_at(r, rec)
+            }
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let (child, r) = Material::mix_child(r, rec, first, second, mask);
+                child.is_opaque_at(&r, rec)
+            }
+            _ => true,
+        }
+    }
+    /// Radiance given off at `rec` towards the origin of `r_in`.
+    pub fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Colour {
+        match self {
+            Material::DiffuseLight { emission } => emission.radiance(r_in.wavelengths().as_ref()),
+            Material::Layered { base, .. }
+            | Material::NormalMapped { base, .. }
+            | Material::Cutout { base, .. } => base.emitted(r_in, rec),
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let weight = Material::mix_weight(mask, rec);
+                (1. - weight) * first.emitted(r_in, rec) + weight * second.emitted(r_in, rec)
+            }
+            _ => Colour::new(0., 0., 0.),
+        }
+    }
+    /// Whether light leaves surfaces of this material in directions depending on its wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Dielectric { refr_index, .. } | Material::Subsurface { refr_index, .. } => {
+                refr_index.is_dispersive()
+            }
+            Material::Layered { base, .. }
+            | Material::NormalMapped { base, .. }
+            | Material::Cutout { base, .. } => base.is_dispersive(),
+            Material::Mix { first, second, .. } => first.is_dispersive() || second.is_dispersive(),
+            _ => false,
+        }
+    }
+    /// Colour of the surface at `rec`, as seen by denoisers and compositing. Clear materials and
+    /// lights are white, so that dividing by their albedo leaves them unchanged.
+    pub fn albedo(&self, rec: &HitRecord) -> Colour {
+        match self {
+            Material::Lambertian { albedo }
+            | Material::OrenNayar { albedo, .. }
+            | Material::Metal { albedo, .. }
+            | Material::Subsurface { albedo, .. } => *albedo,
+            Material::Dielectric { .. } | Material::DiffuseLight { .. } => Colour::new(1., 1., 1.),
+            Material::Principled(principled) => {
+                principled.base_colour.value(rec.u(), rec.v(), &rec.p())
+            }
+            Material::Layered { base, .. }
+            | Material::NormalMapped { base, .. }
+            | Material::Cutout { base, .. } => base.albedo(rec),
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let weight = Material::mix_weight(mask, rec);
+                (1. - weight) * first.albedo(rec) + weight * second.albedo(rec)
+            }
+        }
+    }
+    /// Normal the surface is shaded with at `rec`, once perturbed by any normal map.
+    pub fn shading_normal(&self, rec: &HitRecord) -> Vec3D {
+        match self {
+            Material::NormalMapped { normal_map, .. } => {
+                let mut rec = rec.clone();
+                normal_map.apply(&mut rec);
+                rec.shading_normal()
+            }
+            Material::Cutout { base, .. } => base.shading_normal(rec),
+            _ => rec.shading_normal(),
+        }
+    }
+    fn mix_weight(mask: &Texture, rec: &HitRecord) -> f32 {
+        mask.scalar(rec.u(), rec.v(), &rec.p()).clamp(0., 1.)
+    }
+    /// Child of a mix seen by the ray `r` hitting the surface at `rec`, with the ray to pass on
+    /// to it. Opacity and scattering both go through this, so that a hit sees the same child for
+    /// both. The ray passed on gets a key of its own, keeping choices made in the child
+    /// independent of this one.
+    fn mix_child<'a>(
+        r: &Ray,
+        rec: &HitRecord,
+        first: &'a Material,
+        second: &'a Material,
+        mask: &Texture,
+    ) -> (&'a Material, Ray) {
+        let child = if Material::hit_float(r, rec, 1) < Material::mix_weight(mask, rec) {
+            second
+        } else {
+            first
+        };
+        (child, r.with_key(hash(&[r.key(), 1])))
+    }
+    /// Random number for a choice made where the ray `r` hits the surface at `rec`, `salt`
+    /// telling choices apart. It is hashed from the key of the ray and the hit point, so that it
+    /// differs between samples and seeds but does not depend on the order in which rays are
+    /// traced.
+    fn hit_float(r: &Ray, rec: &HitRecord, salt: u64) -> f32 {
+        let p = rec.p();
+        hash_float(&[
+            r.key(),
+            p.x().to_bits() as u64,
+            p.y().to_bits() as u64,
+            p.z().to_bits() as u64,
+            salt,
+        ])
+    }
+    /// Samples the direction in which a ray hitting the surface at `rec` leaves it, returning
+    /// the scattered ray with its weight. Lobes are widened to the roughness `regularize`, if
+    /// any, see `Bsdf::regularize`.
+    pub fn scatter(
+        &self,
+        r_in: &Ray,
+        mut rec: HitRecord,
+        sampler: &mut dyn Sampler,
+        regularize: Option<f32>,
+    ) -> Option<Scattered> {
+        if let Material::DiffuseLight { .. } = self {
+            return None;
+        }
+        if let Material::NormalMapped { base, normal_map } = self {
+            normal_map.apply(&mut rec);
+            return base.scatter(r_in, rec, sampler, regularize);
+        }
+        if let Material::Cutout { base, .. } = self {
+            return base.scatter(r_in, rec, sampler, regularize);
+        }
+        // anisotropic lobes are stretched along the `u` direction of the surface
+        let mut frame = Onb::from_wu(&rec.shading_normal(), &rec.dpdu());
+        let mut wo = frame.to_local(&-r_in.direction().unit_vector());
+        if wo.z() <= 0. {
+            // the perturbed normal faces away from the viewer, shade with the actual one instead
+            rec.set_shading_normal(&rec.normal());
+            frame = Onb::from_wu(&rec.normal(), &rec.dpdu());
+            wo = frame.to_local(&-r_in.direction().unit_vector());
+        }
+        // when the path carries several wavelengths, dispersive materials scatter the hero one
+        // and the others are dropped
+        let mut wavelengths = r_in.wavelengths();
+        let lambda = wavelengths.map_or(D_LINE, |w| w.hero());
+        if self.is_dispersive() {
+            wavelengths = wavelengths.map(|w| w.terminate_secondary());
+        }
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let mut bsdf = self.bsdf(r_in, &rec, lambda);
+        if let Some(roughness) = regularize {
+            bsdf.regularize(roughness);
+        }
+        let sample = bsdf.sample(&wo, uc, u)?;
+        if sample.pdf <= 0. {
+            return None;
         }
+        // reflected rays stay on the side they came from, while transmitted rays enter the object
+        // through its front face and leave it through the back face, assuming that objects are
+        // surrounded by empty space
+        let wi = frame.to_world(&sample.wi);
+        let (medium, lobe) = if wi.dot(&rec.normal()) > 0. {
+            let lobe = if sample.diffuse {
+                Lobe::Diffuse
+            } else {
+                Lobe::Glossy
+            };
+            (r_in.medium(), lobe)
+        } else if rec.front_face() {
+            (self.interior(), Lobe::Transmission)
+        } else {
+            (None, Lobe::Transmission)
+        };
+        Some(Scattered {
+            ray: r_in
+                .scattered(rec.p(), wi)
+                .with_medium(medium)
+                .with_wavelengths(wavelengths),
+            attenuation: sample.f * sample.wi.z().abs() / sample.pdf,
+            specular: sample.specular,
+            lobe,
+        })
     }
 }
 
-fn shlick(cosine: f32, refr_index: f32) -> f32 {
-    let r0 = ((1. - refr_index) / (1. + refr_index)).powi(2);
-    r0 + (1. - r0) * (1. - cosine).powi(5)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::sample_key;
+    use crate::vec3d::Point3D;
+
+    fn cutout(opacity: f32) -> Material {
+        Material::Cutout {
+            base: Arc::new(Material::Lambertian {
+                albedo: Colour::new(0.5, 0.5, 0.5),
+            }),
+            opacity: Texture::from(opacity),
+        }
+    }
+
+    /// Whether each of the samples of a pixel stops at a cutout hit by its camera ray.
+    fn opaque_samples(material: &Material, seed: u64) -> Vec<bool> {
+        let r = Ray::new(Point3D::new(0., 0., 1.), Vec3D::new(0., 0., -1.), 0.);
+        let mut rec = HitRecord::default();
+        rec.set_normal_face(&r, &Vec3D::new(0., 0., 1.));
+        (0..4000)
+            .map(|index| material.is_opaque_at(&r.with_key(sample_key(seed, (3, 4), index)), &rec))
+            .collect()
+    }
+
+    #[test]
+    fn cutouts_stop_a_fraction_of_rays_given_by_the_opacity() {
+        for opacity in [0., 0.3, 1.] {
+            let samples = opaque_samples(&cutout(opacity), 1);
+            let fraction = samples.iter().filter(|&&opaque| opaque).count() as f32 / 4000.;
+            assert!(
+                (fraction - opacity).abs() < 0.03,
+                "{} {}",
+                fraction,
+                opacity
+            );
+        }
+    }
+
+    #[test]
+    fn mixes_shade_only_the_child_they_pick() {
+        let lambertian = |albedo| Material::Lambertian { albedo };
+        let material = Material::Mix {
+            first: Arc::new(lambertian(Colour::new(1., 0., 0.))),
+            second: Arc::new(Material::Cutout {
+                base: Arc::new(lambertian(Colour::new(0., 0., 1.))),
+                opacity: Texture::from(0.5),
+            }),
+            mask: Texture::from(0.5),
+        };
+        let r = Ray::new(Point3D::new(0., 0., 1.), Vec3D::new(0., 0., -1.), 0.);
+        let mut rec = HitRecord::default();
+        rec.set_normal_face(&r, &Vec3D::new(0., 0., 1.));
+        let z = Vec3D::new(0., 0., 1.);
+        let (mut first, mut second, mut through) = (0, 0, 0);
+        for index in 0..4000 {
+            let r = r.with_key(sample_key(1, (3, 4), index));
+            if !material.is_opaque_at(&r, &rec) {
+                through += 1;
+                continue;
+            }
+            let f = material.bsdf(&r, &rec, D_LINE).eval(&z, &z);
+            if f.x() > 0. && f.z() == 0. {
+                first += 1;
+            } else if f.z() > 0. && f.x() == 0. {
+                second += 1;
+            } else {
+                panic!("hit shaded with both children: {:?}", f);
+            }
+        }
+        // the cutout is picked half the time, and lets half of those rays through
+        for (count, expected) in [(first, 0.5), (second, 0.25), (through, 0.25)] {
+            assert!(
+                (count as f32 / 4000. - expected).abs() < 0.03,
+                "{} {} {}",
+                first,
+                second,
+                through
+            );
+        }
+    }
+
+    #[test]
+    fn cutouts_are_decided_by_the_seed_and_sample() {
+        let material = cutout(0.5);
+        assert_eq!(opaque_samples(&material, 1), opaque_samples(&material, 1));
+        assert_ne!(opaque_samples(&material, 1), opaque_samples(&material, 2));
+    }
 }
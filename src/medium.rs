@@ -0,0 +1,111 @@
+use crate::vec3d::Colour;
+
+/// Homogeneous medium filling the inside of a closed object. Light travelling through it is
+/// attenuated following the Beer–Lambert law, and may also be scattered in random directions.
+#[derive(Debug, Clone, Copy)]
+pub struct Medium {
+    absorption: Colour,
+    scattering: Colour,
+}
+
+/// Outcome of following a ray through a medium, with the throughput weight up to that point.
+pub enum MediumEvent {
+    /// The ray got scattered after travelling `distance`.
+    Scatter { distance: f32, weight: Colour },
+    /// The ray reached the end of the segment.
+    Pass { weight: Colour },
+}
+
+impl Medium {
+    pub fn new(absorption: Colour) -> Medium {
+        Medium {
+            absorption,
+            scattering: Colour::new(0., 0., 0.),
+        }
+    }
+    /// Medium that lets through `transmittance` of the light after travelling `distance`. The
+    /// transmittance must be in (0, 1] in every channel, and the distance positive.
+    pub fn from_transmittance(transmittance: Colour, distance: f32) -> Medium {
+        Medium::new(Colour::new(
+            -transmittance.x().ln() / distance,
+            -transmittance.y().ln() / distance,
+            -transmittance.z().ln() / distance,
+        ))
+    }
+    pub fn with_scattering(absorption: Colour, scattering: Colour) -> Medium {
+        Medium {
+            absorption,
+            scattering,
+        }
+    }
+    /// Scattering medium whose objects appear to have the given albedo once light has bounced
+    /// around inside them, and where light travels `mean_free_path` on average between
+    /// interactions, which must be positive in every channel.
+    pub fn subsurface(albedo: Colour, mean_free_path: Colour) -> Medium {
+        // inversion of the multiple scattering albedo from Chiang et al., "Practical and
+        // Controllable Subsurface Scattering for Production Path Tracing"
+        let single_scattering = |a: f32| {
+            let a = a.clamp(0., 1.);
+            1. - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+        };
+        let extinction = Colour::new(
+            1. / mean_free_path.x(),
+            1. / mean_free_path.y(),
+            1. / mean_free_path.z(),
+        );
+        let scattering = extinction
+            * Colour::new(
+                single_scattering(albedo.x()),
+                single_scattering(albedo.y()),
+                single_scattering(albedo.z()),
+            );
+        Medium {
+            absorption: extinction - scattering,
+            scattering,
+        }
+    }
+    pub fn transmittance(&self, distance: f32) -> Colour {
+        let extinction = self.absorption + self.scattering;
+        Colour::new(
+            (-extinction.x() * distance).exp(),
+            (-extinction.y() * distance).exp(),
+            (-extinction.z() * distance).exp(),
+        )
+    }
+    /// Follows a ray along a segment of length `max_distance`, sampling where it gets scattered
+    /// if it does from the uniform samples `u`. Distances are sampled using the extinction of a
+    /// random channel, weighting by the average density of all three.
+    pub fn sample(&self, max_distance: f32, u: (f32, f32)) -> MediumEvent {
+        if self.scattering == Colour::new(0., 0., 0.) {
+            if max_distance.is_infinite() {
+                return MediumEvent::Pass {
+                    weight: Colour::new(1., 1., 1.),
+                };
+            }
+            return MediumEvent::Pass {
+                weight: self.transmittance(max_distance),
+            };
+        }
+        let extinction = self.absorption + self.scattering;
+        let channel = ((u.0 * 3.) as usize).min(2);
+        let distance = -(1. - u.1).ln() / extinction[channel];
+
+        if distance < max_distance {
+            let transmittance = self.transmittance(distance);
+            let pdf = (extinction * transmittance).mean();
+            return MediumEvent::Scatter {
+                distance,
+                weight: self.scattering * transmittance / pdf,
+            };
+        }
+        if max_distance.is_infinite() {
+            return MediumEvent::Pass {
+                weight: Colour::new(0., 0., 0.),
+            };
+        }
+        let transmittance = self.transmittance(max_distance);
+        MediumEvent::Pass {
+            weight: transmittance / transmittance.mean(),
+        }
+    }
+}
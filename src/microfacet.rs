@@ -0,0 +1,102 @@
+use crate::vec3d::Vec3D;
+use std::f32::consts;
+
+/// Fresnel reflectance of unpolarized light at a smooth dielectric interface, `eta` being the
+/// ratio of the refractive index on the transmitted side to the incident side.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parl * r_parl + r_perp * r_perp)
+}
+
+/// Mirrors `wo` (pointing away from the surface) about the microfacet normal `m`.
+pub fn reflect(wo: &Vec3D, m: &Vec3D) -> Vec3D {
+    2. * wo.dot(m) * m - wo
+}
+
+/// Refracts `wo` (pointing away from the surface) through a microfacet with normal `m`.
+pub fn refract(wo: &Vec3D, m: &Vec3D, eta: f32) -> Option<Vec3D> {
+    let cos_i = wo.dot(m);
+    let sin2_t = (1. - cos_i * cos_i).max(0.) / (eta * eta);
+    if cos_i <= 0. || sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * m)
+}
+
+/// Microfacet normal that refracts `wo` into `wi`, or `None` if there is none facing `wo`.
+pub fn transmission_half_vector(wo: &Vec3D, wi: &Vec3D, eta: f32) -> Option<Vec3D> {
+    let m = wo + wi * eta;
+    if m.length_squared() == 0. {
+        return None;
+    }
+    let m = if m.z() < 0. { -m } else { m }.unit_vector();
+    if wo.dot(&m) <= 0. || wi.dot(&m) >= 0. {
+        return None;
+    }
+    Some(m)
+}
+
+/// Anisotropic GGX normal distribution.
+pub fn ggx_d(m: &Vec3D, alpha_x: f32, alpha_y: f32) -> f32 {
+    if m.z() <= 0. {
+        return 0.;
+    }
+    let e = (m.x() / alpha_x).powi(2) + (m.y() / alpha_y).powi(2) + m.z() * m.z();
+    1. / (consts::PI * alpha_x * alpha_y * e * e)
+}
+
+/// Smith masking term for the anisotropic GGX distribution.
+pub fn ggx_g1(w: &Vec3D, alpha_x: f32, alpha_y: f32) -> f32 {
+    let tan2 = ((w.x() * alpha_x).powi(2) + (w.y() * alpha_y).powi(2)) / (w.z() * w.z());
+    2. / (1. + (1. + tan2).sqrt())
+}
+
+/// Samples a microfacet normal proportionally to `ggx_d(m) * m.z()`.
+pub fn ggx_sample(alpha_x: f32, alpha_y: f32, u1: f32, u2: f32) -> Vec3D {
+    let r = (u1 / (1. - u1)).sqrt();
+    let phi = 2. * consts::PI * u2;
+    Vec3D::new(alpha_x * r * phi.cos(), alpha_y * r * phi.sin(), 1.).unit_vector()
+}
+
+/// Reflectance of a thin film of refractive index `film_ior` and thickness `thickness` (in nm)
+/// lying on a substrate of index `substrate_ior`, lit from empty space at the given wavelength
+/// (in nm). Sums the waves reflected back and forth inside the film, averaging both
+/// polarizations.
+pub fn thin_film_reflectance(
+    cos_i: f32,
+    film_ior: f32,
+    thickness: f32,
+    substrate_ior: f32,
+    wavelength: f32,
+) -> f32 {
+    let sin2_i = (1. - cos_i * cos_i).max(0.);
+    let sin2_f = sin2_i / (film_ior * film_ior);
+    let sin2_s = sin2_i / (substrate_ior * substrate_ior);
+    if sin2_f >= 1. || sin2_s >= 1. {
+        return 1.;
+    }
+    let cos_f = (1. - sin2_f).sqrt();
+    let cos_s = (1. - sin2_s).sqrt();
+    // phase difference between successive reflections from the top and bottom of the film
+    let phase = 4. * consts::PI * film_ior * thickness * cos_f / wavelength;
+    let airy = |r12: f32, r23: f32| {
+        let cross = 2. * r12 * r23 * phase.cos();
+        (r12 * r12 + r23 * r23 + cross) / (1. + r12 * r12 * r23 * r23 + cross)
+    };
+    let s = airy(
+        (cos_i - film_ior * cos_f) / (cos_i + film_ior * cos_f),
+        (film_ior * cos_f - substrate_ior * cos_s) / (film_ior * cos_f + substrate_ior * cos_s),
+    );
+    let p = airy(
+        (film_ior * cos_i - cos_f) / (film_ior * cos_i + cos_f),
+        (substrate_ior * cos_f - film_ior * cos_s) / (substrate_ior * cos_f + film_ior * cos_s),
+    );
+    0.5 * (s + p)
+}
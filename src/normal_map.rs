@@ -0,0 +1,65 @@
+use crate::hittable::HitRecord;
+use crate::onb::Onb;
+use crate::texture::Texture;
+use crate::vec3d::Vec3D;
+
+/// Perturbation of the shading normal that adds surface detail without changing the geometry.
+#[derive(Debug, Clone)]
+pub enum NormalMap {
+    /// Tangent space normals encoded as colours, each channel mapping [0, 1] to [-1, 1]. The
+    /// tangent follows increasing `u` and the bitangent increasing `v`.
+    TangentSpace { normals: Texture },
+    /// Height field displacing the surface along its normal by `scale` times the texture value.
+    Bump { height: Texture, scale: f32 },
+}
+
+impl NormalMap {
+    pub fn apply(&self, rec: &mut HitRecord) {
+        let outward_normal = if rec.front_face() {
+            rec.normal()
+        } else {
+            -rec.normal()
+        };
+        let (u, v, p) = (rec.u(), rec.v(), rec.p());
+        match self {
+            NormalMap::TangentSpace { normals } => {
+                let (tangent, bitangent) = tangent_frame(rec, &outward_normal);
+                let n = 2. * normals.value(u, v, &p) - 1.;
+                let shading_normal = n.x() * tangent + n.y() * bitangent + n.z() * outward_normal;
+                if shading_normal.length_squared() > 0. {
+                    rec.set_shading_normal(&shading_normal.unit_vector());
+                }
+            }
+            NormalMap::Bump { height, scale } => {
+                // finite differences of the displaced surface along u and v
+                let (du, dv) = (0.0005, 0.0005);
+                let displace = scale * height.scalar(u, v, &p);
+                let u_displace = scale * height.scalar(u + du, v, &(p + du * rec.dpdu()));
+                let v_displace = scale * height.scalar(u, v + dv, &(p + dv * rec.dpdv()));
+                let dpdu = rec.dpdu() + (u_displace - displace) / du * outward_normal;
+                let dpdv = rec.dpdv() + (v_displace - displace) / dv * outward_normal;
+                let shading_normal = dpdu.cross(&dpdv);
+                if shading_normal.length_squared() > 0. {
+                    let shading_normal = shading_normal.unit_vector();
+                    // keep the bumps pointing out of the surface
+                    if shading_normal.dot(&outward_normal) < 0. {
+                        rec.set_shading_normal(&-shading_normal);
+                    } else {
+                        rec.set_shading_normal(&shading_normal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tangent and bitangent perpendicular to `normal`, aligned with the surface derivatives where
+/// these are not degenerate.
+fn tangent_frame(rec: &HitRecord, normal: &Vec3D) -> (Vec3D, Vec3D) {
+    let onb = Onb::from_wu(normal, &rec.dpdu());
+    if onb.v().dot(&rec.dpdv()) < 0. {
+        (onb.u(), -onb.v())
+    } else {
+        (onb.u(), onb.v())
+    }
+}
@@ -0,0 +1,67 @@
+use crate::vec3d::Vec3D;
+
+/// Orthonormal basis whose `w` axis is a given direction, usually a surface normal. Local
+/// coordinates are expressed as (u, v, w).
+#[derive(Debug, Clone)]
+pub struct Onb {
+    axes: [Vec3D; 3],
+}
+
+impl Onb {
+    pub fn from_w(w: &Vec3D) -> Onb {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let sign = 1_f32.copysign(w.z());
+        let a = -1. / (sign + w.z());
+        let b = w.x() * w.y() * a;
+        let u = Vec3D::new(1. + sign * w.x() * w.x() * a, sign * b, -sign * w.x());
+        let v = Vec3D::new(b, sign + w.y() * w.y() * a, -w.y());
+        Onb { axes: [u, v, *w] }
+    }
+    /// Basis whose `w` axis is `w` and whose `u` axis follows `u`, made perpendicular to `w`
+    /// (Gram–Schmidt), so that anisotropic lobes line up with a surface tangent. Falls back to
+    /// `from_w` where `u` is zero or parallel to `w`.
+    pub fn from_wu(w: &Vec3D, u: &Vec3D) -> Onb {
+        let u = *u - u.dot(w) * w;
+        if u.length_squared() < 1e-12 {
+            return Onb::from_w(w);
+        }
+        let u = u.unit_vector();
+        Onb {
+            axes: [u, w.cross(&u), *w],
+        }
+    }
+    pub fn u(&self) -> Vec3D {
+        self.axes[0]
+    }
+    pub fn v(&self) -> Vec3D {
+        self.axes[1]
+    }
+    pub fn w(&self) -> Vec3D {
+        self.axes[2]
+    }
+    pub fn to_local(&self, a: &Vec3D) -> Vec3D {
+        Vec3D::new(a.dot(&self.u()), a.dot(&self.v()), a.dot(&self.w()))
+    }
+    pub fn to_world(&self, a: &Vec3D) -> Vec3D {
+        a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basis_follows_the_tangent() {
+        let w = Vec3D::new(1., 2., -2.) / 3.;
+        let onb = Onb::from_wu(&w, &Vec3D::new(1., 0., 0.));
+        assert!(onb.u().dot(&w).abs() < 1e-6 && onb.v().dot(&w).abs() < 1e-6);
+        assert!(onb.u().dot(&onb.v()).abs() < 1e-6);
+        assert!((onb.u().cross(&onb.v()) - w).length() < 1e-6);
+        // the tangent stays in the plane of `w` and the direction it follows
+        assert!(onb.u().x() > 0. && onb.u().dot(&Vec3D::new(0., 1., 1.)).abs() < 1e-6);
+
+        let fallback = Onb::from_wu(&w, &(2. * w));
+        assert!((fallback.u() - Onb::from_w(&w).u()).length() < 1e-6);
+    }
+}
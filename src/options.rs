@@ -0,0 +1,325 @@
+use crate::aov::Aov;
+use crate::filter::Filter;
+use crate::sampler::SamplerKind;
+use crate::tile::TileOrder;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Settings given on the command line.
+#[derive(Debug)]
+pub struct Options {
+    /// trace paths carrying wavelengths instead of RGB colours
+    pub spectral: bool,
+    /// colour temperature in kelvin of the light that should appear white, overriding the one
+    /// from the scene
+    pub white_balance: Option<f32>,
+    /// scene description to render instead of the default scene
+    pub scene: Option<PathBuf>,
+    pub sampler: SamplerKind,
+    /// seed from which all random numbers are derived, making renders reproducible
+    pub seed: u64,
+    /// filter reconstructing pixels from the samples around them
+    pub filter: Filter,
+    /// number of samples taken in each pixel, or the maximum with adaptive sampling
+    pub samples_per_pixel: u32,
+    /// relative error at which adaptive sampling stops sampling a pixel, `None` to always take
+    /// `samples_per_pixel` samples
+    pub adaptive_threshold: Option<f32>,
+    /// number of samples taken in each pixel before adaptive sampling can stop
+    pub min_samples_per_pixel: u32,
+    /// image to write with the number of samples taken in each pixel, white being the maximum
+    pub sample_count_image: Option<PathBuf>,
+    /// width and height in pixels of the tiles the image is divided into
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// file the image is written to instead of the standard output
+    pub output: Option<PathBuf>,
+    /// samples per pixel in each pass over the image, rendering progressively when less than
+    /// `samples_per_pixel`
+    pub pass_samples: Option<u32>,
+    /// seconds after which the render stops, keeping the samples taken so far
+    pub time_limit: Option<f32>,
+    /// seconds between writes of the current estimate to `output`, which is otherwise written
+    /// after each pass
+    pub write_interval: Option<f32>,
+    /// file the state of the render is saved to, when interrupted and every
+    /// `checkpoint_interval` seconds
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: f32,
+    /// continue the render saved in `checkpoint`
+    pub resume: bool,
+    /// output variables to render, with the files they are written to as PFM images
+    pub aovs: Vec<(Aov, PathBuf)>,
+    /// number of samples per pixel averaged in output variables
+    pub aov_samples: u32,
+    /// filter the noise out of the image once rendered
+    pub denoise: bool,
+    pub denoise_strength: f32,
+    pub denoise_iterations: u32,
+    /// largest value of the radiance of a sample seen directly by the camera
+    pub clamp_direct: Option<f32>,
+    /// largest value of the radiance of a sample reaching the camera after a bounce
+    pub clamp_indirect: Option<f32>,
+    /// roughness near-specular lobes are widened to after a rough bounce
+    pub regularize: Option<f32>,
+    /// largest number of rays in a path, the camera ray included
+    pub max_depth: u16,
+    /// largest number of diffuse, glossy, transmission and volume scattering events in a path
+    pub diffuse_bounces: Option<u16>,
+    pub glossy_bounces: Option<u16>,
+    pub transmission_bounces: Option<u16>,
+    pub volume_bounces: Option<u16>,
+    /// number of bounces after which paths are terminated at random with Russian roulette,
+    /// `None` to only stop them at the limits
+    pub roulette_depth: Option<u16>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            spectral: false,
+            white_balance: None,
+            scene: None,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            filter: Filter::Box { radius: 0.5 },
+            samples_per_pixel: 240,
+            adaptive_threshold: None,
+            min_samples_per_pixel: 16,
+            sample_count_image: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            output: None,
+            pass_samples: None,
+            time_limit: None,
+            write_interval: None,
+            checkpoint: None,
+            checkpoint_interval: 300.,
+            resume: false,
+            aovs: Vec::new(),
+            aov_samples: 16,
+            denoise: false,
+            denoise_strength: 1.,
+            denoise_iterations: 5,
+            clamp_direct: None,
+            clamp_indirect: None,
+            regularize: None,
+            max_depth: 36,
+            diffuse_bounces: None,
+            glossy_bounces: None,
+            transmission_bounces: None,
+            volume_bounces: None,
+            roulette_depth: Some(3),
+        }
+    }
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut filter_name: Option<String> = None;
+        let mut filter_radius = None;
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{}`", arg))
+            };
+            match arg.as_str() {
+                "--spectral" => options.spectral = true,
+                "--white-balance" => options.white_balance = Some(parse(&value()?)?),
+                "--scene" => options.scene = Some(PathBuf::from(value()?)),
+                "--sampler" => {
+                    let name = value()?;
+                    options.sampler = SamplerKind::parse(&name)
+                        .ok_or_else(|| format!("unknown sampler `{}`", name))?;
+                }
+                "--seed" => options.seed = parse(&value()?)?,
+                "--spp" => {
+                    options.samples_per_pixel = parse(&value()?)?;
+                    if options.samples_per_pixel == 0 {
+                        return Err("samples per pixel must be positive".to_string());
+                    }
+                }
+                "--min-spp" => options.min_samples_per_pixel = parse(&value()?)?,
+                "--adaptive" => options.adaptive_threshold = Some(parse(&value()?)?),
+                "--spp-image" => options.sample_count_image = Some(PathBuf::from(value()?)),
+                "--tile-size" => {
+                    options.tile_size = parse(&value()?)?;
+                    if options.tile_size == 0 {
+                        return Err("tile size must be positive".to_string());
+                    }
+                }
+                "--tile-order" => {
+                    let name = value()?;
+                    options.tile_order = TileOrder::parse(&name)
+                        .ok_or_else(|| format!("unknown tile order `{}`", name))?;
+                }
+                "--output" | "-o" => options.output = Some(PathBuf::from(value()?)),
+                "--pass-spp" => {
+                    let samples = parse(&value()?)?;
+                    if samples == 0 {
+                        return Err("samples per pass must be positive".to_string());
+                    }
+                    options.pass_samples = Some(samples);
+                }
+                "--time-limit" => options.time_limit = Some(parse_seconds(&value()?)?),
+                "--write-interval" => options.write_interval = Some(parse_seconds(&value()?)?),
+                "--checkpoint" => options.checkpoint = Some(PathBuf::from(value()?)),
+                "--checkpoint-interval" => options.checkpoint_interval = parse_seconds(&value()?)?,
+                "--resume" => options.resume = true,
+                "--aov" => {
+                    let spec = value()?;
+                    let (name, path) = spec.split_once('=').ok_or_else(|| {
+                        format!("expected `NAME=PATH` for `--aov`, got `{}`", spec)
+                    })?;
+                    let aov = Aov::parse(name)
+                        .ok_or_else(|| format!("unknown output variable `{}`", name))?;
+                    options.aovs.push((aov, PathBuf::from(path)));
+                }
+                "--aov-spp" => options.aov_samples = parse(&value()?)?,
+                "--denoise" => options.denoise = true,
+                "--denoise-strength" => options.denoise_strength = parse_positive(&value()?)?,
+                "--denoise-iterations" => options.denoise_iterations = parse(&value()?)?,
+                "--clamp-direct" => options.clamp_direct = Some(parse_positive(&value()?)?),
+                "--clamp-indirect" => options.clamp_indirect = Some(parse_positive(&value()?)?),
+                "--regularize" => options.regularize = Some(parse_positive(&value()?)?),
+                "--max-depth" => {
+                    options.max_depth = parse(&value()?)?;
+                    if options.max_depth == 0 {
+                        return Err("maximum depth must be positive".to_string());
+                    }
+                }
+                "--diffuse-bounces" => options.diffuse_bounces = Some(parse(&value()?)?),
+                "--glossy-bounces" => options.glossy_bounces = Some(parse(&value()?)?),
+                "--transmission-bounces" => options.transmission_bounces = Some(parse(&value()?)?),
+                "--volume-bounces" => options.volume_bounces = Some(parse(&value()?)?),
+                "--roulette-depth" => options.roulette_depth = Some(parse(&value()?)?),
+                "--no-roulette" => options.roulette_depth = None,
+                "--filter" => filter_name = Some(value()?),
+                "--filter-radius" => filter_radius = Some(parse(&value()?)?),
+                _ => return Err(format!("unknown argument `{}`", arg)),
+            }
+        }
+        if options.resume && options.checkpoint.is_none() {
+            return Err("`--resume` needs a `--checkpoint` to resume from".to_string());
+        }
+        if filter_name.is_some() || filter_radius.is_some() {
+            let name = filter_name.as_deref().unwrap_or("box");
+            options.filter = Filter::parse(name, filter_radius)?;
+        }
+        Ok(options)
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}`", value))
+}
+
+fn parse_positive(value: &str) -> Result<f32, String> {
+    match parse(value)? {
+        x if x > 0. && f32::is_finite(x) => Ok(x),
+        _ => Err(format!("expected a positive number, got `{}`", value)),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<f32, String> {
+    match parse(value)? {
+        seconds if seconds >= 0. && f32::is_finite(seconds) => Ok(seconds),
+        _ => Err(format!("invalid duration `{}`", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Options, String> {
+        Options::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_good_arguments() {
+        let options = parse_args(
+            "--spp 64 --adaptive 0.01 --min-spp 8 --sampler halton --seed 5 --filter lanczos \
+             --filter-radius 2 --tile-size 16 --tile-order morton --time-limit 1.5 \
+             --checkpoint out.ckpt --resume --aov normal=n.pfm --max-depth 8 --no-roulette \
+             --regularize 0.3 -o out.ppm",
+        )
+        .unwrap();
+        assert_eq!(options.samples_per_pixel, 64);
+        assert_eq!(options.adaptive_threshold, Some(0.01));
+        assert_eq!(options.min_samples_per_pixel, 8);
+        assert_eq!(options.sampler, SamplerKind::Halton);
+        assert_eq!(options.seed, 5);
+        assert_eq!(options.filter.radius(), 2.);
+        assert_eq!(options.tile_size, 16);
+        assert_eq!(options.time_limit, Some(1.5));
+        assert_eq!(options.tile_order, TileOrder::Morton);
+        assert!(options.resume);
+        assert_eq!(options.aovs, vec![(Aov::Normal, PathBuf::from("n.pfm"))]);
+        assert_eq!(options.max_depth, 8);
+        assert_eq!(options.roulette_depth, None);
+        assert_eq!(options.regularize, Some(0.3));
+        assert_eq!(options.output, Some(PathBuf::from("out.ppm")));
+
+        let defaults = parse_args("").unwrap();
+        assert_eq!(defaults.samples_per_pixel, 240);
+        assert_eq!(defaults.adaptive_threshold, None);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for (args, error) in [
+            ("--frobnicate", "unknown argument `--frobnicate`"),
+            ("--spp", "missing value for `--spp`"),
+            ("--spp many", "invalid value `many`"),
+            ("--spp -1", "invalid value `-1`"),
+            ("--spp 0", "samples per pixel must be positive"),
+            ("--sampler random", "unknown sampler `random`"),
+            ("--filter sinc", "unknown filter `sinc`"),
+            (
+                "--filter-radius 0",
+                "filter radius must be positive, got `0`",
+            ),
+            (
+                "--filter-radius -1",
+                "filter radius must be positive, got `-1`",
+            ),
+            (
+                "--filter-radius NaN",
+                "filter radius must be positive, got `NaN`",
+            ),
+            ("--tile-size 0", "tile size must be positive"),
+            ("--pass-spp 0", "samples per pass must be positive"),
+            ("--max-depth 0", "maximum depth must be positive"),
+            ("--time-limit -3", "invalid duration `-3`"),
+            (
+                "--denoise-strength -1",
+                "expected a positive number, got `-1`",
+            ),
+            ("--clamp-direct -1", "expected a positive number, got `-1`"),
+            (
+                "--clamp-indirect NaN",
+                "expected a positive number, got `NaN`",
+            ),
+            ("--regularize 0", "expected a positive number, got `0`"),
+            (
+                "--denoise-strength NaN",
+                "expected a positive number, got `NaN`",
+            ),
+            (
+                "--aov normal",
+                "expected `NAME=PATH` for `--aov`, got `normal`",
+            ),
+            ("--aov colour=c.pfm", "unknown output variable `colour`"),
+            (
+                "--resume",
+                "`--resume` needs a `--checkpoint` to resume from",
+            ),
+        ] {
+            assert_eq!(parse_args(args).err().as_deref(), Some(error), "{}", args);
+        }
+    }
+}
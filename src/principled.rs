@@ -0,0 +1,353 @@
+use crate::bsdf::{cosine_hemisphere, Bsdf, BsdfSample};
+use crate::hittable::HitRecord;
+use crate::microfacet::{
+    fresnel_dielectric, ggx_d, ggx_g1, ggx_sample, reflect, refract, transmission_half_vector,
+};
+use crate::texture::Texture;
+use crate::vec3d::{Colour, Vec3D};
+use std::f32::consts;
+
+/// Disney's principled BSDF ("Physically Based Shading at Disney", Burley 2012), extended with
+/// rough specular transmission. All parameters other than `base_colour` are in [0, 1]. As in
+/// Burley's model, the diffuse retro-reflection, sheen and clearcoat are added on top of the other
+/// lobes, so rough, bright surfaces can reflect slightly more light than they receive at grazing
+/// angles.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_colour: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    pub specular: Texture,
+    pub specular_tint: Texture,
+    /// stretches highlights along the `u` direction of the surface, from 0 to 1
+    pub anisotropic: Texture,
+    pub sheen: Texture,
+    pub sheen_tint: Texture,
+    pub clearcoat: Texture,
+    pub clearcoat_gloss: Texture,
+    pub specular_transmission: Texture,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_colour: Texture::from(Colour::new(0.8, 0.8, 0.8)),
+            metallic: Texture::from(0.),
+            roughness: Texture::from(0.5),
+            specular: Texture::from(0.5),
+            specular_tint: Texture::from(0.),
+            anisotropic: Texture::from(0.),
+            sheen: Texture::from(0.),
+            sheen_tint: Texture::from(0.5),
+            clearcoat: Texture::from(0.),
+            clearcoat_gloss: Texture::from(1.),
+            specular_transmission: Texture::from(0.),
+        }
+    }
+}
+
+/// Parameters of a `Principled` material evaluated at a hit point, along with the quantities
+/// derived from them.
+pub struct PrincipledBsdf {
+    base_colour: Colour,
+    metallic: f32,
+    roughness: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    clearcoat: f32,
+    specular_transmission: f32,
+    tint: Colour,
+    spec0: Colour,
+    /// GGX roughness along the tangent `dpdu` and the bitangent
+    alpha_x: f32,
+    alpha_y: f32,
+    clearcoat_alpha: f32,
+    /// ratio of the refractive index on the far side of the surface to the near side
+    eta: f32,
+    /// sampling probabilities for the diffuse, specular, clearcoat and transmission lobes
+    weights: [f32; 4],
+}
+
+impl PrincipledBsdf {
+    pub fn at(material: &Principled, rec: &HitRecord) -> PrincipledBsdf {
+        let (u, v, p) = (rec.u(), rec.v(), rec.p());
+        let base_colour = material.base_colour.value(u, v, &p);
+        let metallic = material.metallic.scalar(u, v, &p);
+        let roughness = material.roughness.scalar(u, v, &p);
+        let specular = material.specular.scalar(u, v, &p);
+        let specular_tint = material.specular_tint.scalar(u, v, &p);
+        let anisotropic = material.anisotropic.scalar(u, v, &p);
+        let clearcoat = material.clearcoat.scalar(u, v, &p);
+        let clearcoat_gloss = material.clearcoat_gloss.scalar(u, v, &p);
+        let specular_transmission = material.specular_transmission.scalar(u, v, &p);
+
+        let lum = luminance(&base_colour);
+        let tint = if lum > 0. {
+            base_colour / lum
+        } else {
+            Colour::new(1., 1., 1.)
+        };
+        let spec0 = lerp(
+            lerp(Colour::new(1., 1., 1.), tint, specular_tint) * 0.08 * specular,
+            base_colour,
+            metallic,
+        );
+        let aspect = (1. - 0.9 * anisotropic).sqrt();
+        let alpha = roughness * roughness;
+        // the specular parameter maps to the reflectance at normal incidence of a dielectric
+        let f0 = (0.08 * specular).sqrt().min(0.99);
+        let ior = (1. + f0) / (1. - f0);
+
+        let weights = [
+            (1. - metallic) * (1. - specular_transmission) * lum,
+            luminance(&spec0).max(0.1),
+            0.25 * clearcoat,
+            (1. - metallic) * specular_transmission * lum,
+        ];
+        let total: f32 = weights.iter().sum();
+
+        PrincipledBsdf {
+            base_colour,
+            metallic,
+            roughness,
+            sheen: material.sheen.scalar(u, v, &p),
+            sheen_tint: material.sheen_tint.scalar(u, v, &p),
+            clearcoat,
+            specular_transmission,
+            tint,
+            spec0,
+            alpha_x: (alpha / aspect).max(0.001),
+            alpha_y: (alpha * aspect).max(0.001),
+            clearcoat_alpha: lerp(0.1, 0.001, clearcoat_gloss),
+            eta: if rec.front_face() { ior } else { 1. / ior },
+            weights: [
+                weights[0] / total,
+                weights[1] / total,
+                weights[2] / total,
+                weights[3] / total,
+            ],
+        }
+    }
+
+    fn choose_lobe(&self, u: f32) -> usize {
+        let mut cdf = 0.;
+        for (lobe, weight) in self.weights.iter().enumerate() {
+            cdf += weight;
+            if u < cdf {
+                return lobe;
+            }
+        }
+        self.weights.len() - 1
+    }
+
+    fn eval_transmission(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        let m = match transmission_half_vector(wo, wi, self.eta) {
+            Some(m) => m,
+            None => return Colour::new(0., 0., 0.),
+        };
+        let (cos_om, cos_im) = (wo.dot(&m), wi.dot(&m));
+        let denom = (cos_im + cos_om / self.eta).powi(2) * wi.z().abs() * wo.z();
+        let value = (1. - fresnel_dielectric(cos_om, self.eta))
+            * ggx_d(&m, self.alpha_x, self.alpha_y)
+            * ggx_g1(wo, self.alpha_x, self.alpha_y)
+            * ggx_g1(wi, self.alpha_x, self.alpha_y)
+            * (cos_im * cos_om / denom).abs()
+            / (self.eta * self.eta);
+        (1. - self.metallic) * self.specular_transmission * value * self.base_colour
+    }
+}
+
+impl Bsdf for PrincipledBsdf {
+    /// Picks a lobe according to its weight and samples a direction from it.
+    fn sample(&self, wo: &Vec3D, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let (u1, u2) = u;
+        let lobe = self.choose_lobe(uc);
+        let wi = match lobe {
+            0 => cosine_hemisphere(u),
+            1 => reflect(wo, &ggx_sample(self.alpha_x, self.alpha_y, u1, u2)),
+            2 => reflect(wo, &gtr1_sample(self.clearcoat_alpha, u1, u2)),
+            _ => refract(
+                wo,
+                &ggx_sample(self.alpha_x, self.alpha_y, u1, u2),
+                self.eta,
+            )?,
+        };
+        // the pdf only accounts for reflection lobes sampling the upper hemisphere and the
+        // transmission lobe sampling the lower one
+        if (lobe == 3) != (wi.z() < 0.) {
+            return None;
+        }
+
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi),
+            pdf,
+            specular: false,
+            diffuse: lobe == 0,
+        })
+    }
+
+    fn eval(&self, wo: &Vec3D, wi: &Vec3D) -> Colour {
+        if wo.z() <= 0. || wi.z() == 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        if wi.z() < 0. {
+            return self.eval_transmission(wo, wi);
+        }
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        let h = (wo + wi).unit_vector();
+        let cos_d = wi.dot(&h);
+
+        // Burley diffuse with retro-reflection, plus sheen at grazing angles
+        let fd90 = 0.5 + 2. * cos_d * cos_d * self.roughness;
+        let fd =
+            (1. + (fd90 - 1.) * schlick_weight(cos_i)) * (1. + (fd90 - 1.) * schlick_weight(cos_o));
+        let sheen_colour = lerp(Colour::new(1., 1., 1.), self.tint, self.sheen_tint);
+        let dielectric = (1. - self.metallic) * (1. - self.specular_transmission);
+        let diffuse = dielectric
+            * (fd * consts::FRAC_1_PI * self.base_colour
+                + self.sheen * schlick_weight(cos_d) * sheen_colour);
+
+        let fresnel = lerp(self.spec0, Colour::new(1., 1., 1.), schlick_weight(cos_d));
+        let specular = fresnel
+            * ggx_d(&h, self.alpha_x, self.alpha_y)
+            * ggx_g1(wo, self.alpha_x, self.alpha_y)
+            * ggx_g1(wi, self.alpha_x, self.alpha_y)
+            / (4. * cos_i * cos_o);
+
+        let clearcoat = 0.25
+            * self.clearcoat
+            * lerp(0.04, 1., schlick_weight(cos_d))
+            * gtr1_d(h.z(), self.clearcoat_alpha)
+            * ggx_g1(wo, 0.25, 0.25)
+            * ggx_g1(wi, 0.25, 0.25)
+            / (4. * cos_i * cos_o);
+
+        diffuse + specular + clearcoat
+    }
+
+    fn pdf(&self, wo: &Vec3D, wi: &Vec3D) -> f32 {
+        if wo.z() <= 0. || wi.z() == 0. {
+            return 0.;
+        }
+        if wi.z() < 0. {
+            return match transmission_half_vector(wo, wi, self.eta) {
+                Some(m) => {
+                    let (cos_om, cos_im) = (wo.dot(&m), wi.dot(&m));
+                    let jacobian = cos_im.abs() / (cos_im + cos_om / self.eta).powi(2);
+                    self.weights[3] * ggx_d(&m, self.alpha_x, self.alpha_y) * m.z() * jacobian
+                }
+                None => 0.,
+            };
+        }
+        let h = (wo + wi).unit_vector();
+        let jacobian = 1. / (4. * wo.dot(&h));
+        self.weights[0] * wi.z() * consts::FRAC_1_PI
+            + self.weights[1] * ggx_d(&h, self.alpha_x, self.alpha_y) * h.z() * jacobian
+            + self.weights[2] * gtr1_d(h.z(), self.clearcoat_alpha) * h.z() * jacobian
+    }
+    fn regularize(&mut self, roughness: f32) {
+        let alpha = roughness * roughness;
+        self.alpha_x = self.alpha_x.max(alpha);
+        self.alpha_y = self.alpha_y.max(alpha);
+        self.clearcoat_alpha = self.clearcoat_alpha.max(alpha);
+    }
+}
+
+fn luminance(c: &Colour) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn lerp<T>(a: T, b: T, t: f32) -> T
+where
+    T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+{
+    a * (1. - t) + b * t
+}
+
+fn schlick_weight(cos: f32) -> f32 {
+    (1. - cos).clamp(0., 1.).powi(5)
+}
+
+/// Generalized-Trowbridge-Reitz distribution with exponent 1, used for the clearcoat.
+fn gtr1_d(cos_m: f32, alpha: f32) -> f32 {
+    if cos_m <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    (a2 - 1.) / (consts::PI * a2.ln() * (1. + (a2 - 1.) * cos_m * cos_m))
+}
+
+/// Samples a microfacet normal proportionally to `gtr1_d(m.z()) * m.z()`.
+fn gtr1_sample(alpha: f32, u1: f32, u2: f32) -> Vec3D {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1. - a2.powf(1. - u1)) / (1. - a2)).max(0.).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * consts::PI * u2;
+    Vec3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::tests::{check_bsdf, check_bsdf_within, outgoing_directions};
+    use crate::ray::Ray;
+    use crate::vec3d::Point3D;
+
+    fn hit(front_face: bool) -> HitRecord {
+        let mut rec = HitRecord::default();
+        let direction = Vec3D::new(0., 0., if front_face { -1. } else { 1. });
+        let r = Ray::new(Point3D::new(0., 0., 0.), direction, 0.);
+        rec.set_normal_face(&r, &Vec3D::new(0., 0., 1.));
+        rec
+    }
+
+    #[test]
+    fn reflection_lobes_are_consistent() {
+        let materials = [
+            Principled::default(),
+            Principled {
+                metallic: Texture::from(1.),
+                roughness: Texture::from(0.4),
+                anisotropic: Texture::from(0.8),
+                ..Principled::default()
+            },
+            Principled {
+                sheen: Texture::from(1.),
+                ..Principled::default()
+            },
+            Principled {
+                clearcoat: Texture::from(1.),
+                clearcoat_gloss: Texture::from(0.),
+                ..Principled::default()
+            },
+        ];
+        for material in &materials {
+            let bsdf = PrincipledBsdf::at(material, &hit(true));
+            for wo in outgoing_directions() {
+                check_bsdf(&bsdf, &wo);
+            }
+        }
+    }
+
+    #[test]
+    fn transmission_is_consistent() {
+        let material = Principled {
+            base_colour: Texture::from(Colour::new(1., 1., 1.)),
+            specular_transmission: Texture::from(1.),
+            ..Principled::default()
+        };
+        for front_face in [true, false] {
+            let bsdf = PrincipledBsdf::at(&material, &hit(front_face));
+            // light leaving the denser medium gains radiance as it spreads over a narrower cone
+            let max_albedo = 1f32.max(1. / (bsdf.eta * bsdf.eta));
+            for wo in outgoing_directions() {
+                check_bsdf_within(&bsdf, &wo, max_albedo);
+            }
+        }
+    }
+}
@@ -1,10 +1,17 @@
+use crate::medium::Medium;
+use crate::sampler::hash;
+use crate::spectrum::Wavelengths;
 use crate::vec3d::{Point3D, Vec3D};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     origin: Point3D,
     direction: Vec3D,
     time: f32,
+    medium: Option<Medium>,
+    wavelengths: Option<Wavelengths>,
+    /// seed of the random choices made along the ray that are not drawn from the sampler
+    key: u64,
 }
 
 impl Ray {
@@ -13,8 +20,36 @@ impl Ray {
             origin,
             direction,
             time,
+            medium: None,
+            wavelengths: None,
+            key: 0,
         }
     }
+    /// Ray leaving `origin` in `direction` at the same time and carrying the same wavelengths as
+    /// this one, in the same medium until changed. Its key is derived from this one.
+    pub fn scattered(&self, origin: Point3D, direction: Vec3D) -> Ray {
+        Ray {
+            origin,
+            direction,
+            key: hash(&[self.key]),
+            ..*self
+        }
+    }
+    /// Sets the medium the ray is travelling through. `None` means empty space.
+    pub fn with_medium(mut self, medium: Option<Medium>) -> Ray {
+        self.medium = medium;
+        self
+    }
+    /// Sets the wavelengths followed by the ray when rendering spectrally.
+    pub fn with_wavelengths(mut self, wavelengths: Option<Wavelengths>) -> Ray {
+        self.wavelengths = wavelengths;
+        self
+    }
+    /// Sets the key random choices along the ray are hashed from, see `sample_key`.
+    pub fn with_key(mut self, key: u64) -> Ray {
+        self.key = key;
+        self
+    }
     pub fn origin(&self) -> Point3D {
         self.origin
     }
@@ -24,6 +59,15 @@ impl Ray {
     pub fn time(&self) -> f32 {
         self.time
     }
+    pub fn medium(&self) -> Option<Medium> {
+        self.medium
+    }
+    pub fn wavelengths(&self) -> Option<Wavelengths> {
+        self.wavelengths
+    }
+    pub fn key(&self) -> u64 {
+        self.key
+    }
     pub fn at(&self, t: f32) -> Point3D {
         self.origin + self.direction * t
     }
@@ -0,0 +1,455 @@
+use crate::vec3d::Vec3D;
+use std::f32::consts;
+
+/// Source of the uniform random numbers used to build a path. Each call hands out the next
+/// dimension of the current sample, so that samplers can spread the values of a dimension evenly
+/// over the samples of a pixel.
+pub trait Sampler {
+    /// Starts the `index`-th sample of `pixel`, going back to the first dimension.
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+/// The available sample generators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn parse(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+    /// Sampler for pixels taking `samples_per_pixel` samples. The numbers it hands out only
+    /// depend on `seed`, the pixel and the sample index, whichever thread asks for them.
+    pub fn create(self, samples_per_pixel: usize, seed: u64) -> PixelSampler {
+        match self {
+            SamplerKind::Independent => PixelSampler::Independent(IndependentSampler {
+                seed,
+                rng: fastrand::Rng::new(),
+            }),
+            SamplerKind::Stratified => PixelSampler::Stratified(StratifiedSampler {
+                samples_per_pixel: samples_per_pixel.max(1),
+                state: PixelSample::new(seed),
+                rng: fastrand::Rng::new(),
+            }),
+            SamplerKind::Halton => PixelSampler::Halton(HaltonSampler {
+                samples_per_pixel: samples_per_pixel.max(1),
+                state: PixelSample::new(seed),
+            }),
+            SamplerKind::Sobol => PixelSampler::Sobol(SobolSampler {
+                state: PixelSample::new(seed),
+            }),
+        }
+    }
+}
+
+/// Sampler of any of the `SamplerKind`s, held by value.
+pub enum PixelSampler {
+    Independent(IndependentSampler),
+    Stratified(StratifiedSampler),
+    Halton(HaltonSampler),
+    Sobol(SobolSampler),
+}
+
+impl Sampler for PixelSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        match self {
+            PixelSampler::Independent(sampler) => sampler.start_pixel_sample(pixel, index),
+            PixelSampler::Stratified(sampler) => sampler.start_pixel_sample(pixel, index),
+            PixelSampler::Halton(sampler) => sampler.start_pixel_sample(pixel, index),
+            PixelSampler::Sobol(sampler) => sampler.start_pixel_sample(pixel, index),
+        }
+    }
+    fn get_1d(&mut self) -> f32 {
+        match self {
+            PixelSampler::Independent(sampler) => sampler.get_1d(),
+            PixelSampler::Stratified(sampler) => sampler.get_1d(),
+            PixelSampler::Halton(sampler) => sampler.get_1d(),
+            PixelSampler::Sobol(sampler) => sampler.get_1d(),
+        }
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        match self {
+            PixelSampler::Independent(sampler) => sampler.get_2d(),
+            PixelSampler::Stratified(sampler) => sampler.get_2d(),
+            PixelSampler::Halton(sampler) => sampler.get_2d(),
+            PixelSampler::Sobol(sampler) => sampler.get_2d(),
+        }
+    }
+}
+
+/// Uniformly distributed point on the unit disk in the xy plane, using the concentric mapping
+/// from Shirley and Chiu, "A Low Distortion Map Between Disk and Square", which preserves the
+/// stratification of the samples.
+pub fn sample_disk(u: (f32, f32)) -> Vec3D {
+    let (x, y) = (2. * u.0 - 1., 2. * u.1 - 1.);
+    if x == 0. && y == 0. {
+        return Vec3D::new(0., 0., 0.);
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, consts::FRAC_PI_4 * (y / x))
+    } else {
+        (y, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (x / y))
+    };
+    Vec3D::new(r * theta.cos(), r * theta.sin(), 0.)
+}
+
+/// Uniformly distributed direction.
+pub fn sample_sphere(u: (f32, f32)) -> Vec3D {
+    let z = 1. - 2. * u.0;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * consts::PI * u.1;
+    Vec3D::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Independent uniform random numbers, the plain Monte Carlo baseline.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: fastrand::Rng,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.rng = seeded_rng(hash(&[
+            self.seed,
+            pixel.0 as u64,
+            pixel.1 as u64,
+            index as u64,
+        ]));
+    }
+    fn get_1d(&mut self) -> f32 {
+        self.rng.f32()
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.f32(), self.rng.f32())
+    }
+}
+
+/// Position of the sampler within the samples of a pixel.
+#[derive(Debug)]
+struct PixelSample {
+    /// seed of the whole render
+    render_seed: u64,
+    /// seed derived from the pixel, decorrelating neighbouring pixels
+    seed: u64,
+    index: usize,
+    dimension: u64,
+}
+
+impl PixelSample {
+    fn new(render_seed: u64) -> PixelSample {
+        PixelSample {
+            render_seed,
+            seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+    fn start(&mut self, pixel: (usize, usize), index: usize) {
+        self.seed = hash(&[self.render_seed, pixel.0 as u64, pixel.1 as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+    /// Seed for the next dimension.
+    fn next_dimension(&mut self) -> u64 {
+        self.dimension += 1;
+        hash(&[self.seed, self.dimension])
+    }
+}
+
+/// Jittered sampling, where each dimension is split into as many strata as there are samples
+/// per pixel (or a grid of them in 2D), and every sample falls in a different stratum. Strata
+/// are matched to samples by a random permutation for each dimension.
+pub struct StratifiedSampler {
+    samples_per_pixel: usize,
+    state: PixelSample,
+    rng: fastrand::Rng,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.state.start(pixel, index);
+        self.rng = seeded_rng(hash(&[self.state.seed, index as u64]));
+    }
+    fn get_1d(&mut self) -> f32 {
+        let n = self.samples_per_pixel;
+        let seed = self.state.next_dimension();
+        let stratum = permute(self.state.index % n, n, seed);
+        ((stratum as f32 + self.rng.f32()) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        let nx = (self.samples_per_pixel as f32).sqrt().ceil() as usize;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let seed = self.state.next_dimension();
+        let stratum = permute(self.state.index % (nx * ny), nx * ny, seed);
+        (
+            (((stratum % nx) as f32 + self.rng.f32()) / nx as f32).min(ONE_MINUS_EPSILON),
+            (((stratum / nx) as f32 + self.rng.f32()) / ny as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// Halton points over the samples of each pixel. Every pair of dimensions uses the points in
+/// bases 2 and 3, with its own random toroidal shift and shuffling of the sample order, as the
+/// sequence correlates badly in the larger bases that further dimensions would need.
+pub struct HaltonSampler {
+    samples_per_pixel: usize,
+    state: PixelSample,
+}
+
+impl HaltonSampler {
+    fn point(&mut self, base: u64, seed: u64) -> f32 {
+        let n = self.samples_per_pixel;
+        let index = permute(self.state.index % n, n, seed) + self.state.index / n * n;
+        let shift = to_unit(hash(&[seed, base]) as u32);
+        (radical_inverse(index as u64, base) + shift)
+            .fract()
+            .min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f32 {
+        let seed = self.state.next_dimension();
+        self.point(2, seed)
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        let seed = self.state.next_dimension();
+        (self.point(2, seed), self.point(3, seed))
+    }
+}
+
+fn radical_inverse(mut index: u64, base: u64) -> f32 {
+    let inverse_base = 1. / base as f64;
+    let mut factor = inverse_base;
+    let mut result = 0.;
+    while index > 0 {
+        result += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result as f32
+}
+
+/// Owen-scrambled Sobol points over the samples of each pixel, following Burley, "Practical
+/// Hash-based Owen Scrambling". Every pair of dimensions uses the first two Sobol dimensions
+/// with its own scrambling and shuffling of the sample order, which keeps the distribution good
+/// for any number of dimensions.
+pub struct SobolSampler {
+    state: PixelSample,
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f32 {
+        let seed = self.state.next_dimension() as u32;
+        let index = nested_uniform_scramble(self.state.index as u32, seed);
+        to_unit(nested_uniform_scramble(
+            sobol(index, 0),
+            hash(&[seed as u64, 0]) as u32,
+        ))
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        let seed = self.state.next_dimension() as u32;
+        let index = nested_uniform_scramble(self.state.index as u32, seed);
+        (
+            to_unit(nested_uniform_scramble(
+                sobol(index, 0),
+                hash(&[seed as u64, 0]) as u32,
+            )),
+            to_unit(nested_uniform_scramble(
+                sobol(index, 1),
+                hash(&[seed as u64, 1]) as u32,
+            )),
+        )
+    }
+}
+
+/// Point `index` of the first or second dimension of the Sobol sequence, as a 32-bit fraction.
+fn sobol(index: u32, dimension: usize) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut v = 1 << 31;
+    let mut x = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            x ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    x
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Element `i` of a random permutation of `0..n` picked by `seed`, from Kensler, "Correlated
+/// Multi-Jittered Sampling".
+fn permute(i: usize, n: usize, seed: u64) -> usize {
+    let (mut i, l, p) = (i as u32, n as u32, seed as u32);
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p) % l) as usize
+}
+
+/// Largest float below one, keeping samples in [0, 1).
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+
+/// Converts a 32-bit fraction to a float in [0, 1).
+fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Generator of the random numbers drawn from `seed`.
+pub fn seeded_rng(seed: u64) -> fastrand::Rng {
+    let rng = fastrand::Rng::new();
+    rng.seed(seed);
+    rng
+}
+
+/// Key of the camera ray of the `index`-th sample of `pixel`, from which the random choices
+/// along its path that are not drawn from the sampler are hashed.
+pub fn sample_key(seed: u64, pixel: (usize, usize), index: usize) -> u64 {
+    hash(&[seed, pixel.0 as u64, pixel.1 as u64, index as u64])
+}
+
+/// Uniform value in [0, 1) hashed from the given values.
+pub fn hash_float(values: &[u64]) -> f32 {
+    to_unit(hash(values) as u32)
+}
+
+/// Mixes the given values into a well distributed 64-bit hash, using the finalizer of
+/// SplitMix64.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        let mut x = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn values(sampler: &mut PixelSampler, pixel: (usize, usize), index: usize) -> Vec<f32> {
+        sampler.start_pixel_sample(pixel, index);
+        let mut values = vec![];
+        for _ in 0..4 {
+            values.push(sampler.get_1d());
+            let (u, v) = sampler.get_2d();
+            values.extend([u, v]);
+        }
+        values
+    }
+
+    #[test]
+    fn values_only_depend_on_seed_pixel_and_index() {
+        for kind in KINDS {
+            let mut forward = kind.create(16, 1);
+            let mut backward = kind.create(16, 1);
+            let mut reseeded = kind.create(16, 2);
+            let samples: Vec<_> = (0..3)
+                .flat_map(|x| (0..20).map(move |index| ((x, 5), index)))
+                .collect();
+            let first: Vec<_> = samples
+                .iter()
+                .map(|&(pixel, index)| values(&mut forward, pixel, index))
+                .collect();
+            for (&(pixel, index), expected) in samples.iter().zip(&first).rev() {
+                let value = values(&mut backward, pixel, index);
+                assert_eq!(&value, expected, "{:?}", kind);
+                assert!(value.iter().all(|v| (0. ..1.).contains(v)), "{:?}", kind);
+                assert_ne!(values(&mut reseeded, pixel, index), value, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_of_a_pixel_are_stratified() {
+        let n = 16;
+        for kind in &KINDS[1..] {
+            let mut sampler = kind.create(n, 3);
+            let mut strata_1d = vec![0; n];
+            let mut strata_2d = vec![0; n];
+            for index in 0..n {
+                sampler.start_pixel_sample((7, 9), index);
+                strata_1d[(sampler.get_1d() * n as f32) as usize] += 1;
+                let (u, v) = sampler.get_2d();
+                let stratum = if *kind == SamplerKind::Halton {
+                    // the points in base 3 only fill strata of powers of three
+                    (u * n as f32) as usize
+                } else {
+                    (u * 4.) as usize + 4 * (v * 4.) as usize
+                };
+                strata_2d[stratum] += 1;
+            }
+            assert!(strata_1d.iter().all(|&count| count == 1), "{:?}", kind);
+            assert!(strata_2d.iter().all(|&count| count == 1), "{:?}", kind);
+        }
+    }
+}
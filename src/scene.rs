@@ -0,0 +1,620 @@
+use crate::emission::Emission;
+use crate::hittable::{Hittable, HittableList, Sphere};
+use crate::image::Image;
+use crate::ior::{Ior, D_LINE};
+use crate::layered::{Coat, ThinFilm};
+use crate::library::MaterialLibrary;
+use crate::material::Material;
+use crate::medium::Medium;
+use crate::normal_map::NormalMap;
+use crate::principled::Principled;
+use crate::sampler::hash;
+use crate::texture::Texture;
+use crate::vec3d::{Colour, Point3D};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Everything to render besides the camera.
+pub struct Scene {
+    pub world: HittableList,
+    pub materials: MaterialLibrary,
+    /// colour temperature in kelvin of the light that should appear white
+    pub white_balance: Option<f32>,
+    /// hash of the files the scene was loaded from, telling apart different versions of them
+    pub digest: Option<u64>,
+}
+
+impl Scene {
+    /// The scene from the cover of "Ray Tracing in One Weekend", with small spheres placed
+    /// randomly from `seed`.
+    pub fn random(seed: u64) -> Scene {
+        let rng = fastrand::Rng::new();
+        rng.seed(seed);
+        let random_colour = |min: f32, max: f32| {
+            Colour::new(
+                min + (max - min) * rng.f32(),
+                min + (max - min) * rng.f32(),
+                min + (max - min) * rng.f32(),
+            )
+        };
+        let mut materials = MaterialLibrary::presets();
+        let ground_radius = 300.;
+        let mut objects: Vec<Box<dyn Hittable>> = vec![];
+        let ground = materials.add(
+            "ground",
+            Material::Lambertian {
+                albedo: Colour::new(0.5, 0.5, 0.5),
+            },
+        );
+        objects.push(Box::new(Sphere::new(
+            Point3D::new(0., -ground_radius, 0.),
+            ground_radius,
+            ground,
+        )));
+        let clear_glass = materials.add(
+            "clear_glass",
+            Material::Dielectric {
+                refr_index: Ior::Constant(1.5),
+                medium: None,
+            },
+        );
+        (-11..11).for_each(|a| {
+            (-11..11).for_each(|b| {
+                let choose_mat = rng.f32();
+                let radius = 0.15 + 0.1 * rng.f32();
+                let x = a as f32 + 0.9 * rng.f32();
+                let y = b as f32 + 0.9 * rng.f32();
+                // offset so that the spheres stick to the ground
+                let offset = (ground_radius.powi(2) - x.powi(2) - y.powi(2)).sqrt();
+                let z = -ground_radius + offset + radius;
+                let center = Point3D::new(x, z, y);
+                if (center - Point3D::new(4., 0.2, 0.)).length() > 0.9 {
+                    let name = format!("sphere_{}_{}", a, b);
+                    let material = if choose_mat < 0.5 {
+                        materials.add(
+                            &name,
+                            Material::Lambertian {
+                                albedo: random_colour(0., 1.),
+                            },
+                        )
+                    } else if choose_mat < 0.85 {
+                        materials.add(
+                            &name,
+                            Material::Metal {
+                                albedo: random_colour(0., 1.),
+                                fuzziness: 0.5 * rng.f32(),
+                            },
+                        )
+                    } else if choose_mat < 0.95 {
+                        Arc::clone(&clear_glass)
+                    } else {
+                        // coloured glass
+                        materials.add(
+                            &name,
+                            Material::Dielectric {
+                                refr_index: Ior::Constant(1.5),
+                                medium: Some(Medium::from_transmittance(
+                                    random_colour(0.2, 1.),
+                                    2. * radius,
+                                )),
+                            },
+                        )
+                    };
+                    objects.push(Box::new(Sphere::new(center, radius, material)));
+                }
+            })
+        });
+        let brown = materials.add(
+            "brown",
+            Material::Lambertian {
+                albedo: Colour::new(0.4, 0.2, 0.1),
+            },
+        );
+        objects.push(Box::new(Sphere::new(Point3D::new(-4., 1., 0.), 1., brown)));
+        let bronze = materials.add(
+            "bronze",
+            Material::Metal {
+                albedo: Colour::new(0.7, 0.6, 0.5),
+                fuzziness: 0.,
+            },
+        );
+        objects.push(Box::new(Sphere::new(Point3D::new(4., 1., 0.), 1., bronze)));
+        let glass = materials.add(
+            "glass",
+            Material::Dielectric {
+                refr_index: Ior::Constant(1.5),
+                medium: None,
+            },
+        );
+        objects.push(Box::new(Sphere::new(Point3D::new(0., 1., 0.), 1., glass)));
+
+        Scene {
+            world: HittableList::new(objects),
+            materials,
+            white_balance: None,
+            digest: None,
+        }
+    }
+    /// Reads a scene description, a text file with one statement per line and comments starting
+    /// with `#`:
+    ///
+    /// ```text
+    /// texture <name> <texture>
+    /// material <name> lambertian <colour>
+    /// material <name> oren_nayar <colour> <sigma>
+    /// material <name> metal <colour> <fuzziness>
+    /// material <name> dielectric <ior> [transmittance <colour> <distance>]
+    ///                                  [medium <absorption colour> <scattering colour>]
+    /// material <name> subsurface <albedo colour> <mean free path colour> <ior>
+    /// material <name> principled [<parameter> <texture>]...
+    /// material <name> light <colour>
+    /// material <name> blackbody <kelvin> <intensity>
+    /// material <name> layered <base> <ior> <roughness> [thickness <thickness>]
+    ///                         [absorption <colour>] [film <ior> <thickness in nm>]
+    /// material <name> normal_map <base> <texture>
+    /// material <name> bump <base> <texture> <scale>
+    /// material <name> cutout <base> <opacity texture>
+    /// material <name> mix <first> <second> <mask texture>
+    /// sphere <x> <y> <z> <radius> <material name>
+    /// moving_sphere <x0> <y0> <z0> <x1> <y1> <z1> <radius> <material name>
+    /// white_balance <kelvin>
+    /// ```
+    ///
+    /// where a `<colour>` is three numbers, an `<ior>` is a number, `bk7`, `diamond`,
+    /// `abbe <nd> <vd>`, `cauchy <a> <b>` or `sellmeier <b1> <b2> <b3> <c1> <c2> <c3>`, and a
+    /// `<texture>` is a number, the name of a texture, `solid <colour>`,
+    /// `checker <odd colour> <even colour> <scale>` or `image <PPM file>`, found relative to the
+    /// scene file. The parameters of `principled` materials are those of `Principled`, and
+    /// materials are referred to by name once defined. Moving spheres go from the first centre
+    /// to the second while the shutter is open. Numbers must be finite, and radii, distances
+    /// and refractive indices positive.
+    ///
+    /// Materials from `MaterialLibrary::presets` can be used without being defined.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Scene> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut loader = Loader {
+            scene: Scene {
+                world: HittableList::new(vec![]),
+                materials: MaterialLibrary::presets(),
+                white_balance: None,
+                digest: Some(hash_bytes(text.as_bytes())),
+            },
+            textures: HashMap::new(),
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            loader.statement(&tokens).map_err(|msg| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", i + 1, msg),
+                )
+            })?;
+        }
+        Ok(loader.scene)
+    }
+}
+
+/// State of a scene description being read.
+struct Loader {
+    scene: Scene,
+    textures: HashMap<String, Texture>,
+    /// directory of the scene file, which the paths of images are relative to
+    directory: PathBuf,
+}
+
+impl Loader {
+    fn statement(&mut self, tokens: &[&str]) -> Result<(), String> {
+        let mut args = Arguments { tokens, pos: 1 };
+        match tokens[0] {
+            "texture" => {
+                let name = args.word("texture name")?;
+                let texture = self.texture(&mut args)?;
+                self.textures.insert(name.to_string(), texture);
+            }
+            "material" => {
+                let name = args.word("material name")?;
+                let material = self.material(&mut args)?;
+                self.scene.materials.add(name, material);
+            }
+            "sphere" => {
+                let center = args.point()?;
+                let radius = args.positive("radius")?;
+                let material = self.material_ref(&mut args)?;
+                self.scene
+                    .world
+                    .add(Box::new(Sphere::new(center, radius, material)));
+            }
+            "moving_sphere" => {
+                let center0 = args.point()?;
+                let center1 = args.point()?;
+                let radius = args.positive("radius")?;
+                let material = self.material_ref(&mut args)?;
+                self.scene
+                    .world
+                    .add(Box::new(Sphere::moving(center0, center1, radius, material)));
+            }
+            "white_balance" => self.scene.white_balance = Some(args.number()?),
+            _ => return Err(format!("invalid statement `{}`", tokens.join(" "))),
+        }
+        args.finish()
+    }
+    fn material(&mut self, args: &mut Arguments) -> Result<Material, String> {
+        Ok(match args.word("material type")? {
+            "lambertian" => Material::Lambertian {
+                albedo: args.colour()?,
+            },
+            "oren_nayar" => Material::OrenNayar {
+                albedo: args.colour()?,
+                sigma: args.number()?,
+            },
+            "metal" => Material::Metal {
+                albedo: args.colour()?,
+                fuzziness: args.number()?,
+            },
+            "dielectric" => {
+                let refr_index = args.ior()?;
+                let medium = match args.option(&["transmittance", "medium"]) {
+                    Some("transmittance") => {
+                        let transmittance = args.colour()?;
+                        if !(0..3).all(|c| transmittance[c] > 0. && transmittance[c] <= 1.) {
+                            return Err("the transmittance must be in (0, 1]".to_string());
+                        }
+                        let distance = args.positive("transmittance distance")?;
+                        Some(Medium::from_transmittance(transmittance, distance))
+                    }
+                    Some(_) => {
+                        let absorption = args.colour()?;
+                        Some(Medium::with_scattering(absorption, args.colour()?))
+                    }
+                    None => None,
+                };
+                Material::Dielectric { refr_index, medium }
+            }
+            "subsurface" => {
+                let albedo = args.colour()?;
+                let mean_free_path = args.colour()?;
+                if !(0..3).all(|c| mean_free_path[c] > 0. && mean_free_path[c].is_finite()) {
+                    return Err("the mean free path must be positive".to_string());
+                }
+                Material::Subsurface {
+                    albedo,
+                    mean_free_path,
+                    refr_index: args.ior()?,
+                }
+            }
+            "principled" => {
+                let mut principled = Principled::default();
+                while let Some(parameter) = args.next() {
+                    let value = match parameter {
+                        "base_colour" => &mut principled.base_colour,
+                        "metallic" => &mut principled.metallic,
+                        "roughness" => &mut principled.roughness,
+                        "specular" => &mut principled.specular,
+                        "specular_tint" => &mut principled.specular_tint,
+                        "anisotropic" => &mut principled.anisotropic,
+                        "sheen" => &mut principled.sheen,
+                        "sheen_tint" => &mut principled.sheen_tint,
+                        "clearcoat" => &mut principled.clearcoat,
+                        "clearcoat_gloss" => &mut principled.clearcoat_gloss,
+                        "specular_transmission" => &mut principled.specular_transmission,
+                        _ => return Err(format!("unknown principled parameter `{}`", parameter)),
+                    };
+                    *value = self.texture(args)?;
+                }
+                Material::Principled(principled)
+            }
+            "light" => Material::DiffuseLight {
+                emission: Emission::rgb(args.colour()?),
+            },
+            "blackbody" => Material::DiffuseLight {
+                emission: Emission::blackbody(args.number()?, args.number()?),
+            },
+            "layered" => {
+                let base = self.material_ref(args)?;
+                let mut coat = Coat::new(args.positive("refractive index")?, args.number()?);
+                while let Some(option) = args.option(&["thickness", "absorption", "film"]) {
+                    match option {
+                        "thickness" => coat.thickness = args.number()?,
+                        "absorption" => coat.absorption = args.colour()?,
+                        _ => {
+                            coat.thin_film = Some(ThinFilm {
+                                refr_index: args.positive("refractive index")?,
+                                thickness: args.number()?,
+                            })
+                        }
+                    }
+                }
+                Material::Layered { base, coat }
+            }
+            "normal_map" => Material::NormalMapped {
+                base: self.material_ref(args)?,
+                normal_map: NormalMap::TangentSpace {
+                    normals: self.texture(args)?,
+                },
+            },
+            "bump" => Material::NormalMapped {
+                base: self.material_ref(args)?,
+                normal_map: NormalMap::Bump {
+                    height: self.texture(args)?,
+                    scale: args.number()?,
+                },
+            },
+            "cutout" => Material::Cutout {
+                base: self.material_ref(args)?,
+                opacity: self.texture(args)?,
+            },
+            "mix" => Material::Mix {
+                first: self.material_ref(args)?,
+                second: self.material_ref(args)?,
+                mask: self.texture(args)?,
+            },
+            kind => return Err(format!("unknown material type `{}`", kind)),
+        })
+    }
+    fn material_ref(&self, args: &mut Arguments) -> Result<Arc<Material>, String> {
+        let name = args.word("material name")?;
+        self.scene
+            .materials
+            .get(name)
+            .ok_or_else(|| format!("unknown material `{}`", name))
+    }
+    fn texture(&mut self, args: &mut Arguments) -> Result<Texture, String> {
+        let token = args.word("texture")?;
+        if let Ok(value) = token.parse::<f32>() {
+            if !value.is_finite() {
+                return Err(format!("invalid number `{}`", token));
+            }
+            return Ok(Texture::from(value));
+        }
+        Ok(match token {
+            "solid" => Texture::from(args.colour()?),
+            "checker" => Texture::Checker {
+                odd: args.colour()?,
+                even: args.colour()?,
+                scale: args.number()?,
+            },
+            "image" => {
+                let path = self.directory.join(args.word("image path")?);
+                let cannot_read = |err| format!("cannot read {}: {}", path.display(), err);
+                let image = Image::read_ppm(&path).map_err(cannot_read)?;
+                let bytes = fs::read(&path).map_err(cannot_read)?;
+                self.scene.digest = self
+                    .scene
+                    .digest
+                    .map(|digest| hash(&[digest, hash_bytes(&bytes)]));
+                Texture::Image {
+                    image: Arc::new(image),
+                }
+            }
+            name => self
+                .textures
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown texture `{}`", name))?,
+        })
+    }
+}
+
+/// Arguments of a statement, read one after the other.
+struct Arguments<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Arguments<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+    fn word(&mut self, what: &str) -> Result<&'a str, String> {
+        self.next().ok_or_else(|| format!("missing {}", what))
+    }
+    fn number(&mut self) -> Result<f32, String> {
+        let token = self.word("number")?;
+        match token.parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(format!("invalid number `{}`", token)),
+        }
+    }
+    fn positive(&mut self, what: &str) -> Result<f32, String> {
+        match self.number()? {
+            value if value > 0. => Ok(value),
+            _ => Err(format!("the {} must be positive", what)),
+        }
+    }
+    fn colour(&mut self) -> Result<Colour, String> {
+        Ok(Colour::new(self.number()?, self.number()?, self.number()?))
+    }
+    fn point(&mut self) -> Result<Point3D, String> {
+        self.colour()
+    }
+    fn ior(&mut self) -> Result<Ior, String> {
+        let ior = match self.word("refractive index")? {
+            "bk7" => Ior::BK7,
+            "diamond" => Ior::DIAMOND,
+            "abbe" => Ior::Abbe {
+                nd: self.positive("refractive index")?,
+                vd: self.positive("Abbe number")?,
+            },
+            "cauchy" => Ior::Cauchy {
+                a: self.number()?,
+                b: self.number()?,
+            },
+            "sellmeier" => Ior::Sellmeier {
+                b: [self.number()?, self.number()?, self.number()?],
+                c: [self.number()?, self.number()?, self.number()?],
+            },
+            token => Ior::Constant(
+                token
+                    .parse()
+                    .map_err(|_| format!("invalid refractive index `{}`", token))?,
+            ),
+        };
+        let n = ior.at(D_LINE);
+        if !(n > 0. && n.is_finite()) {
+            return Err("the refractive index must be positive".to_string());
+        }
+        Ok(ior)
+    }
+    /// Takes the next argument if it is one of the keywords `options`.
+    fn option(&mut self, options: &[&str]) -> Option<&'a str> {
+        let token = *self.tokens.get(self.pos)?;
+        if !options.contains(&token) {
+            return None;
+        }
+        self.pos += 1;
+        Some(token)
+    }
+    fn finish(&self) -> Result<(), String> {
+        match self.tokens.get(self.pos) {
+            Some(token) => Err(format!("unexpected `{}`", token)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Hash of the contents of a file.
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let words = bytes.chunks(8).map(|chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        u64::from_le_bytes(word)
+    });
+    hash(
+        &std::iter::once(bytes.len() as u64)
+            .chain(words)
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> io::Result<Scene> {
+        let path = std::env::temp_dir().join(format!("traycer-{}-{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let scene = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn loads_every_statement() {
+        let scene = load(
+            "good.scene",
+            "# a comment\n\
+             texture tiles checker 0 0 0 1 1 1 4\n\
+             material floor principled base_colour tiles roughness 0.5\n\
+             material coat layered floor 1.5 0.1 film 1.33 400\n\
+             material leaf cutout coat 0.5  # trailing comment\n\
+             material blend mix gold leaf tiles\n\
+             material lamp blackbody 3000 10\n\
+             material flint dielectric abbe 1.62 36 transmittance 0.8 0.9 1 2\n\
+             sphere 0 -100 0 100 floor\n\
+             sphere 0 1 0 1 blend\n\
+             moving_sphere 2 1 0 2 1.5 0 0.5 flint\n\
+             sphere 0 5 0 0.5 lamp\n\
+             white_balance 5000\n",
+        )
+        .unwrap();
+        for name in ["floor", "coat", "leaf", "blend", "lamp", "flint", "glass"] {
+            assert!(scene.materials.get(name).is_some(), "{}", name);
+        }
+        assert_eq!(scene.white_balance, Some(5000.));
+        assert!(scene.digest.is_some());
+    }
+
+    #[test]
+    fn random_scene_names_its_materials() {
+        let scene = Scene::random(1);
+        for name in ["ground", "clear_glass", "brown", "bronze", "glass", "gold"] {
+            assert!(scene.materials.get(name).is_some(), "{}", name);
+        }
+        assert_eq!(scene.digest, None);
+    }
+
+    #[test]
+    fn rejects_bad_statements() {
+        for (text, error) in [
+            (
+                "cube 0 0 0 1 glass",
+                "line 1: invalid statement `cube 0 0 0 1 glass`",
+            ),
+            (
+                "\nsphere 0 0 0 1 marble",
+                "line 2: unknown material `marble`",
+            ),
+            ("sphere 0 0 0 1 glass shiny", "line 1: unexpected `shiny`"),
+            ("sphere 0 zero 0 1 glass", "line 1: invalid number `zero`"),
+            ("sphere 0 0 0 1", "line 1: missing material name"),
+            ("material m metal 1 1 1", "line 1: missing number"),
+            (
+                "material m lambertian wood",
+                "line 1: invalid number `wood`",
+            ),
+            (
+                "material m principled roughness bumpy",
+                "line 1: unknown texture `bumpy`",
+            ),
+            (
+                "material m subsurface 1 1 1 0.1 0 0.1 1.4",
+                "line 1: the mean free path must be positive",
+            ),
+            (
+                "material m subsurface 1 1 1 0.1 inf 0.1 1.4",
+                "line 1: invalid number `inf`",
+            ),
+            (
+                "material m dielectric 1.5 transmittance 0.5 0 0.5 1",
+                "line 1: the transmittance must be in (0, 1]",
+            ),
+            (
+                "material m dielectric 1.5 transmittance 0.5 1.2 0.5 1",
+                "line 1: the transmittance must be in (0, 1]",
+            ),
+            (
+                "material m dielectric 1.5 transmittance 0.5 0.5 0.5 -1",
+                "line 1: the transmittance distance must be positive",
+            ),
+            (
+                "material m dielectric -1.5",
+                "line 1: the refractive index must be positive",
+            ),
+            (
+                "material m dielectric abbe 0 40",
+                "line 1: the refractive index must be positive",
+            ),
+            (
+                "material m layered glass 0 0.1",
+                "line 1: the refractive index must be positive",
+            ),
+            (
+                "sphere 0 0 0 0 glass",
+                "line 1: the radius must be positive",
+            ),
+            (
+                "moving_sphere 0 0 0 1 0 0 -1 glass",
+                "line 1: the radius must be positive",
+            ),
+            ("material m metal 1 NaN 1 0", "line 1: invalid number `NaN`"),
+            (
+                "material m principled roughness inf",
+                "line 1: invalid number `inf`",
+            ),
+        ] {
+            let err = load("bad.scene", text).err().expect(text);
+            assert_eq!(err.to_string(), error, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,536 @@
+use crate::vec3d::Colour;
+use std::sync::OnceLock;
+
+/// Range of wavelengths (in nm) followed by spectral rendering.
+pub const LAMBDA_MIN: f32 = 360.;
+pub const LAMBDA_MAX: f32 = 830.;
+
+/// Wavelengths carried by a path in spectral mode. The first one is the hero wavelength, the
+/// others are evenly spaced rotations of it over the visible range. While a path carries
+/// wavelengths, the three components of each `Colour` along it hold values at those
+/// wavelengths rather than red, green and blue.
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    lambda: [f32; 3],
+    pdf: [f32; 3],
+}
+
+impl Wavelengths {
+    /// Samples the hero wavelength from `u`, with a density roughly following the sensitivity
+    /// of the eye.
+    pub fn sample(u: f32) -> Wavelengths {
+        let mut lambda = [0.; 3];
+        let mut pdf = [0.; 3];
+        for i in 0..3 {
+            let u = (u + i as f32 / 3.).fract();
+            lambda[i] = sample_visible(u);
+            pdf[i] = visible_pdf(lambda[i]);
+        }
+        Wavelengths { lambda, pdf }
+    }
+    pub fn lambda(&self, i: usize) -> f32 {
+        self.lambda[i]
+    }
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+    /// Keeps only the hero wavelength, for paths whose direction depends on it. Its density is
+    /// adjusted to carry the weight of all three.
+    pub fn terminate_secondary(self) -> Wavelengths {
+        Wavelengths {
+            lambda: self.lambda,
+            pdf: [self.pdf[0] / 3., 0., 0.],
+        }
+    }
+    /// Values of a reflectance given in linear sRGB at the sampled wavelengths.
+    pub fn reflectance(&self, rgb: &Colour) -> Colour {
+        let r = (rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.));
+        if r.0 == r.1 && r.1 == r.2 {
+            return Colour::new(r.0, r.0, r.0);
+        }
+        let max = r.0.max(r.1).max(r.2);
+        // reflectances above one are scaled down into the range covered by the table
+        let scale = if max > 1. { 2. * max } else { 1. };
+        let coefficients = tables()
+            .rgb_to_spectrum
+            .lookup(r.0 / scale, r.1 / scale, r.2 / scale);
+        self.map(|lambda| scale * sigmoid_polynomial(&coefficients, lambda))
+    }
+    /// Values of an emission spectrum given in linear sRGB at the sampled wavelengths. The
+    /// spectrum is the reflectance matching `rgb` lit by the D65 white point.
+    pub fn illuminant(&self, rgb: &Colour) -> Colour {
+        let r = (rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.));
+        let max = r.0.max(r.1).max(r.2);
+        if max == 0. {
+            return Colour::new(0., 0., 0.);
+        }
+        let tables = tables();
+        let scale = 2. * max;
+        let coefficients = tables
+            .rgb_to_spectrum
+            .lookup(r.0 / scale, r.1 / scale, r.2 / scale);
+        self.map(|lambda| {
+            scale * sigmoid_polynomial(&coefficients, lambda) * d65(lambda) / tables.d65_norm
+        })
+    }
+    /// Converts the radiance carried at the sampled wavelengths to linear sRGB.
+    pub fn to_rgb(self, radiance: &Colour) -> Colour {
+        let mut xyz = Colour::new(0., 0., 0.);
+        for i in 0..3 {
+            if self.pdf[i] > 0. {
+                xyz += radiance[i] * cie_xyz(self.lambda[i]) / self.pdf[i];
+            }
+        }
+        xyz_to_rgb(&(xyz / (3. * tables().cie_y_integral)))
+    }
+    fn map<F: Fn(f32) -> f32>(&self, f: F) -> Colour {
+        Colour::new(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+}
+
+/// Inverse CDF of `visible_pdf`, from "Physically Based Rendering" (Pharr et al.).
+fn sample_visible(u: f32) -> f32 {
+    538. - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh()
+}
+
+fn visible_pdf(lambda: f32) -> f32 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.;
+    }
+    0.003_939_804 / (0.0072 * (lambda - 538.)).cosh().powi(2)
+}
+
+/// CIE 1931 colour matching functions, using the multi-lobe fit from Wyman et al., "Simple
+/// Analytic Approximations to the CIE XYZ Color Matching Functions".
+pub fn cie_xyz(lambda: f32) -> Colour {
+    let g = |mu: f32, sigma1: f32, sigma2: f32| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    Colour::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_rgb(xyz: &Colour) -> Colour {
+    Colour::new(
+        3.240_454_2 * xyz.x() - 1.537_138_5 * xyz.y() - 0.498_531_4 * xyz.z(),
+        -0.969_266 * xyz.x() + 1.876_010_8 * xyz.y() + 0.041_556 * xyz.z(),
+        0.055_643_4 * xyz.x() - 0.204_025_9 * xyz.y() + 1.057_225_2 * xyz.z(),
+    )
+}
+
+pub fn rgb_to_xyz(rgb: &Colour) -> Colour {
+    Colour::new(
+        0.412_456_4 * rgb.x() + 0.357_576_1 * rgb.y() + 0.180_437_5 * rgb.z(),
+        0.212_672_9 * rgb.x() + 0.715_152_2 * rgb.y() + 0.072_175 * rgb.z(),
+        0.019_333_9 * rgb.x() + 0.119_192 * rgb.y() + 0.950_304_1 * rgb.z(),
+    )
+}
+
+/// Spectral radiance of a black body at `temperature` kelvin, at the wavelength `lambda` in nm.
+pub fn planck(lambda: f32, temperature: f32) -> f32 {
+    const C: f64 = 299_792_458.;
+    const H: f64 = 6.626_070_15e-34;
+    const K: f64 = 1.380_649e-23;
+    let l = lambda as f64 * 1e-9;
+    (2. * H * C * C / (l.powi(5) * ((H * C / (l * K * temperature as f64)).exp() - 1.))) as f32
+}
+
+/// Colour of a black body at `temperature` kelvin in CIE XYZ, scaled to a luminance of one.
+pub fn blackbody_xyz(temperature: f32) -> Colour {
+    let mut xyz = Colour::new(0., 0., 0.);
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        xyz += planck(lambda, temperature) * cie_xyz(lambda);
+        lambda += 1.;
+    }
+    if xyz.y() > 0. {
+        xyz / xyz.y()
+    } else {
+        xyz
+    }
+}
+
+/// Linear map between colours, in row-major order.
+#[derive(Debug, Clone, Copy)]
+pub struct ColourMatrix([Colour; 3]);
+
+impl ColourMatrix {
+    pub fn identity() -> ColourMatrix {
+        ColourMatrix([
+            Colour::new(1., 0., 0.),
+            Colour::new(0., 1., 0.),
+            Colour::new(0., 0., 1.),
+        ])
+    }
+    /// Von Kries adaptation in the Bradford cone space, mapping linear sRGB colours shot under a
+    /// light at `temperature` kelvin to how they would appear under the D65 white point.
+    pub fn white_balance(temperature: f32) -> ColourMatrix {
+        let bradford = ColourMatrix([
+            Colour::new(0.8951, 0.2664, -0.1614),
+            Colour::new(-0.7502, 1.7135, 0.0367),
+            Colour::new(0.0389, -0.0685, 1.0296),
+        ]);
+        let inverse_bradford = ColourMatrix([
+            Colour::new(0.986_993, -0.147_054_3, 0.159_962_7),
+            Colour::new(0.432_305_3, 0.518_360_3, 0.049_291_2),
+            Colour::new(-0.008_528_7, 0.040_042_8, 0.968_486_7),
+        ]);
+        let source = bradford.apply(&blackbody_xyz(temperature));
+        let target = bradford.apply(&rgb_to_xyz(&Colour::new(1., 1., 1.)));
+        let scale = ColourMatrix([
+            Colour::new(target.x() / source.x(), 0., 0.),
+            Colour::new(0., target.y() / source.y(), 0.),
+            Colour::new(0., 0., target.z() / source.z()),
+        ]);
+        let xyz_to_rgb = ColourMatrix([
+            xyz_to_rgb(&Colour::new(1., 0., 0.)),
+            xyz_to_rgb(&Colour::new(0., 1., 0.)),
+            xyz_to_rgb(&Colour::new(0., 0., 1.)),
+        ])
+        .transpose();
+        let rgb_to_xyz = ColourMatrix([
+            rgb_to_xyz(&Colour::new(1., 0., 0.)),
+            rgb_to_xyz(&Colour::new(0., 1., 0.)),
+            rgb_to_xyz(&Colour::new(0., 0., 1.)),
+        ])
+        .transpose();
+        xyz_to_rgb
+            .mul(&inverse_bradford)
+            .mul(&scale)
+            .mul(&bradford)
+            .mul(&rgb_to_xyz)
+    }
+    pub fn apply(&self, c: &Colour) -> Colour {
+        Colour::new(self.0[0].dot(c), self.0[1].dot(c), self.0[2].dot(c))
+    }
+    pub fn mul(&self, other: &ColourMatrix) -> ColourMatrix {
+        let columns = other.transpose();
+        ColourMatrix([0, 1, 2].map(|i| columns.apply(&self.0[i])))
+    }
+    pub fn transpose(&self) -> ColourMatrix {
+        let m = &self.0;
+        ColourMatrix([
+            Colour::new(m[0].x(), m[1].x(), m[2].x()),
+            Colour::new(m[0].y(), m[1].y(), m[2].y()),
+            Colour::new(m[0].z(), m[1].z(), m[2].z()),
+        ])
+    }
+}
+
+/// Relative spectral power of the CIE D65 illuminant, tabulated every 10 nm from `LAMBDA_MIN`.
+const D65: [f32; 48] = [
+    46.64, 52.09, 49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92,
+    108.81, 109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01,
+    89.60, 87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89,
+    75.09, 63.59, 46.42, 66.81, 63.38, 64.30, 59.45, 51.96, 57.44, 60.31,
+];
+
+pub fn d65(lambda: f32) -> f32 {
+    let x = ((lambda - LAMBDA_MIN) / 10.).clamp(0., (D65.len() - 1) as f32);
+    let i = (x as usize).min(D65.len() - 2);
+    let t = x - i as f32;
+    (1. - t) * D65[i] + t * D65[i + 1]
+}
+
+/// Smooth spectrum bounded by [0, 1], following Jakob and Hanika, "A Low-Dimensional Function
+/// Space for Efficient Spectral Upsampling". The polynomial is in terms of the wavelength
+/// normalized over the visible range.
+fn sigmoid_polynomial(c: &[f32; 3], lambda: f32) -> f32 {
+    let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+    let x = (c[0] * t + c[1]) * t + c[2];
+    if x.is_infinite() {
+        return if x > 0. { 1. } else { 0. };
+    }
+    0.5 + x / (2. * (1. + x * x).sqrt())
+}
+
+struct Tables {
+    rgb_to_spectrum: RgbToSpectrum,
+    /// integral of the luminance matching function, which normalizes XYZ values so that a
+    /// constant spectrum of one has a luminance of one
+    cie_y_integral: f32,
+    /// scale making the D65 spectrum have a luminance of one
+    d65_norm: f32,
+}
+
+/// Tables are computed on first use, which only happens when rendering spectrally.
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut cie_y_integral = 0.;
+        let mut d65_y_integral = 0.;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            cie_y_integral += cie_xyz(lambda).y();
+            d65_y_integral += d65(lambda) * cie_xyz(lambda).y();
+            lambda += 1.;
+        }
+        Tables {
+            rgb_to_spectrum: RgbToSpectrum::fit(32),
+            cie_y_integral,
+            d65_norm: d65_y_integral / cie_y_integral,
+        }
+    })
+}
+
+/// Sigmoid polynomial coefficients fitted on a regular grid over the unit RGB cube.
+struct RgbToSpectrum {
+    resolution: usize,
+    coefficients: Vec<[f32; 3]>,
+}
+
+impl RgbToSpectrum {
+    fn fit(resolution: usize) -> RgbToSpectrum {
+        let fitter = Fitter::new();
+        let n = resolution;
+        let index = |i: usize, j: usize, k: usize| (i * n + j) * n + k;
+        let mut coefficients = vec![[0.; 3]; n * n * n];
+
+        // fit from the middle of the cube outwards, starting each fit from the solution of a
+        // neighbour closer to the middle, as fits towards the edges are hard to get right from
+        // scratch
+        let centre = (n - 1) as f32 / 2.;
+        let mut order: Vec<(usize, usize, usize)> = (0..n)
+            .flat_map(|i| (0..n).flat_map(move |j| (0..n).map(move |k| (i, j, k))))
+            .collect();
+        let distance = |(i, j, k): (usize, usize, usize)| {
+            let d = |x: usize| (x as f32 - centre).abs();
+            d(i).max(d(j)).max(d(k))
+        };
+        order.sort_by(|a, b| distance(*a).partial_cmp(&distance(*b)).unwrap());
+        let towards_centre = |x: usize| {
+            if x as f32 - centre > 0.5 {
+                x - 1
+            } else if centre - x as f32 > 0.5 {
+                x + 1
+            } else {
+                x
+            }
+        };
+
+        for (i, j, k) in order {
+            let start =
+                coefficients[index(towards_centre(i), towards_centre(j), towards_centre(k))];
+            let target = [i, j, k].map(|x| x as f64 / (n - 1) as f64);
+            coefficients[index(i, j, k)] = fitter.solve(target, start);
+        }
+        RgbToSpectrum {
+            resolution,
+            coefficients,
+        }
+    }
+    /// Trilinear interpolation of the coefficients at the given colour.
+    fn lookup(&self, r: f32, g: f32, b: f32) -> [f32; 3] {
+        let n = self.resolution;
+        let cell = |x: f32| {
+            let x = x.clamp(0., 1.) * (n - 1) as f32;
+            let i = (x as usize).min(n - 2);
+            (i, x - i as f32)
+        };
+        let ((i, ti), (j, tj), (k, tk)) = (cell(r), cell(g), cell(b));
+        let mut result = [0.; 3];
+        for (di, wi) in [(0, 1. - ti), (1, ti)] {
+            for (dj, wj) in [(0, 1. - tj), (1, tj)] {
+                for (dk, wk) in [(0, 1. - tk), (1, tk)] {
+                    let c = &self.coefficients[((i + di) * n + j + dj) * n + k + dk];
+                    for (r, c) in result.iter_mut().zip(c) {
+                        *r += wi * wj * wk * c;
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Gauss-Newton solver finding the sigmoid polynomial of a reflectance that appears as a given
+/// colour under D65.
+struct Fitter {
+    /// normalized wavelengths at which the spectrum is integrated
+    t: Vec<f64>,
+    /// contribution to linear sRGB of each wavelength for a unit reflectance
+    weights: Vec<[f64; 3]>,
+}
+
+impl Fitter {
+    fn new() -> Fitter {
+        let lambdas: Vec<f32> = (0..=((LAMBDA_MAX - LAMBDA_MIN) as usize / 5))
+            .map(|i| LAMBDA_MIN + 5. * i as f32)
+            .collect();
+        let norm: f64 = lambdas
+            .iter()
+            .map(|&l| (d65(l) * cie_xyz(l).y()) as f64)
+            .sum();
+        Fitter {
+            t: lambdas
+                .iter()
+                .map(|&l| ((l - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)) as f64)
+                .collect(),
+            weights: lambdas
+                .iter()
+                .map(|&l| {
+                    let rgb = xyz_to_rgb(&(d65(l) * cie_xyz(l)));
+                    [
+                        rgb.x() as f64 / norm,
+                        rgb.y() as f64 / norm,
+                        rgb.z() as f64 / norm,
+                    ]
+                })
+                .collect(),
+        }
+    }
+    /// Colour of the spectrum with the given coefficients, along with its Jacobian.
+    fn evaluate(&self, c: &[f64; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+        let mut rgb = [0.; 3];
+        let mut jacobian = [[0.; 3]; 3];
+        for (t, w) in self.t.iter().zip(&self.weights) {
+            let x = (c[0] * t + c[1]) * t + c[2];
+            let root = (1. + x * x).sqrt();
+            let s = 0.5 + x / (2. * root);
+            let ds = 1. / (2. * root * root * root);
+            let dx = [t * t, *t, 1.];
+            for i in 0..3 {
+                rgb[i] += w[i] * s;
+                for j in 0..3 {
+                    jacobian[i][j] += w[i] * ds * dx[j];
+                }
+            }
+        }
+        (rgb, jacobian)
+    }
+    fn solve(&self, target: [f64; 3], start: [f32; 3]) -> [f32; 3] {
+        // colours on the faces of the cube can only be reached in the limit
+        let target = target.map(|x| x.clamp(0.001, 0.999));
+        let mut c = start.map(|x| x as f64);
+        let mut damping = 1e-3;
+        let error = |rgb: &[f64; 3]| -> f64 { (0..3).map(|i| (rgb[i] - target[i]).powi(2)).sum() };
+        let (mut rgb, mut jacobian) = self.evaluate(&c);
+        for _ in 0..64 {
+            let residual = [rgb[0] - target[0], rgb[1] - target[1], rgb[2] - target[2]];
+            if error(&rgb) < 1e-12 {
+                break;
+            }
+            // Levenberg-Marquardt step solving (J^T J + damping I) delta = -J^T residual
+            let mut a = [[0.; 3]; 3];
+            let mut b = [0.; 3];
+            for i in 0..3 {
+                for j in 0..3 {
+                    a[i][j] = (0..3).map(|k| jacobian[k][i] * jacobian[k][j]).sum();
+                }
+                a[i][i] += damping;
+                b[i] = -(0..3).map(|k| jacobian[k][i] * residual[k]).sum::<f64>();
+            }
+            let delta = match solve3(&a, &b) {
+                Some(delta) => delta,
+                None => break,
+            };
+            let candidate = [c[0] + delta[0], c[1] + delta[1], c[2] + delta[2]];
+            let (candidate_rgb, candidate_jacobian) = self.evaluate(&candidate);
+            if error(&candidate_rgb) < error(&rgb) {
+                c = candidate;
+                rgb = candidate_rgb;
+                jacobian = candidate_jacobian;
+                damping = (damping * 0.5).max(1e-9);
+            } else {
+                damping *= 10.;
+            }
+        }
+        c.map(|x| x as f32)
+    }
+}
+
+/// Solves a 3x3 linear system with Cramer's rule.
+fn solve3(a: &[[f64; 3]; 3], b: &[f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(a);
+    if d.abs() < 1e-300 {
+        return None;
+    }
+    let mut x = [0.; 3];
+    for (col, x) in x.iter_mut().enumerate() {
+        let mut m = *a;
+        for row in 0..3 {
+            m[row][col] = b[row];
+        }
+        *x = det(&m) / d;
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Average over stratified wavelength samples of the radiance given by `spectrum` at each,
+    /// converted back to linear sRGB.
+    fn average_rgb<F: Fn(&Wavelengths) -> Colour>(spectrum: F) -> Colour {
+        let n = 20_000;
+        let mut sum = Colour::new(0., 0., 0.);
+        for i in 0..n {
+            let wavelengths = Wavelengths::sample((i as f32 + 0.5) / n as f32);
+            sum += wavelengths.to_rgb(&spectrum(&wavelengths));
+        }
+        sum / n as f32
+    }
+
+    #[test]
+    fn grey_fits_a_flat_spectrum() {
+        for grey in [0.2, 0.5, 0.8] {
+            let c = tables().rgb_to_spectrum.lookup(grey, grey, grey);
+            let mut lambda = LAMBDA_MIN;
+            while lambda <= LAMBDA_MAX {
+                let value = sigmoid_polynomial(&c, lambda);
+                assert!((value - grey).abs() < 0.01, "{} {} {}", grey, lambda, value);
+                lambda += 10.;
+            }
+        }
+        let white = Wavelengths::sample(0.3).reflectance(&Colour::new(1., 1., 1.));
+        assert_eq!(white, Colour::new(1., 1., 1.));
+    }
+
+    #[test]
+    fn colours_round_trip() {
+        // saturated primaries are outside what smooth spectra can reach, but come close
+        for rgb in [
+            Colour::new(1., 1., 1.),
+            Colour::new(0.2, 0.5, 0.7),
+            Colour::new(1., 0., 0.),
+            Colour::new(0., 1., 0.),
+            Colour::new(0., 0., 1.),
+        ] {
+            // as a light, and as a reflectance lit by a white light
+            let light = average_rgb(|w| w.illuminant(&rgb));
+            let white = Colour::new(1., 1., 1.);
+            let lit = average_rgb(|w| w.reflectance(&rgb) * w.illuminant(&white));
+            for result in [light, lit] {
+                assert!((result - rgb).length() < 0.01, "{:?} {:?}", rgb, result);
+            }
+        }
+    }
+
+    #[test]
+    fn constant_spectra_converge_to_their_colour() {
+        let mut xyz = Colour::new(0., 0., 0.);
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            xyz += cie_xyz(lambda);
+            lambda += 1.;
+        }
+        let expected = xyz_to_rgb(&(0.7 * xyz / tables().cie_y_integral));
+        let average = average_rgb(|_| Colour::new(0.7, 0.7, 0.7));
+        assert!(
+            (average - expected).length() < 1e-3,
+            "{:?} {:?}",
+            average,
+            expected
+        );
+    }
+}
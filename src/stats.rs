@@ -0,0 +1,249 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io::Write;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counts of the work done by a render. Each thread counts into its own copy, which workers
+/// take with `take_local` once done so that counting never contends between threads.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderStats {
+    /// rays leaving the camera
+    pub camera_rays: u64,
+    /// rays traced to find the closest hit, including camera rays
+    pub rays: u64,
+    /// ray–primitive intersection tests
+    pub intersection_tests: u64,
+    /// samples whose radiance was not a finite number
+    pub invalid_samples: u64,
+}
+
+thread_local! {
+    static LOCAL: Cell<RenderStats> = Cell::new(RenderStats::default());
+}
+
+fn count(f: impl FnOnce(&mut RenderStats)) {
+    LOCAL.with(|local| {
+        let mut stats = local.get();
+        f(&mut stats);
+        local.set(stats);
+    });
+}
+
+pub fn count_camera_ray() {
+    count(|stats| stats.camera_rays += 1);
+}
+
+pub fn count_ray() {
+    count(|stats| stats.rays += 1);
+}
+
+pub fn count_intersection_test() {
+    count(|stats| stats.intersection_tests += 1);
+}
+
+pub fn count_invalid_sample() {
+    count(|stats| stats.invalid_samples += 1);
+}
+
+/// Counts of the calling thread since the last call, resetting them.
+pub fn take_local() -> RenderStats {
+    LOCAL.with(|local| local.take())
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: RenderStats) {
+        self.camera_rays += other.camera_rays;
+        self.rays += other.rays;
+        self.intersection_tests += other.intersection_tests;
+        self.invalid_samples += other.invalid_samples;
+    }
+}
+
+impl RenderStats {
+    /// Report of the counts for a render that took `elapsed`.
+    pub fn report(&self, elapsed: Duration) -> Report {
+        Report {
+            stats: *self,
+            elapsed,
+        }
+    }
+}
+
+pub struct Report {
+    stats: RenderStats,
+    elapsed: Duration,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats = &self.stats;
+        let ratio = |a: u64, b: u64| if b == 0 { 0. } else { a as f64 / b as f64 };
+        writeln!(
+            f,
+            "Render time:             {:.2} s",
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(f, "Rays:                    {}", stats.rays)?;
+        writeln!(f, "  camera:                {}", stats.camera_rays)?;
+        writeln!(
+            f,
+            "  secondary:             {}",
+            stats.rays.saturating_sub(stats.camera_rays)
+        )?;
+        writeln!(
+            f,
+            "Rays per second:         {:.0}",
+            stats.rays as f64 / self.elapsed.as_secs_f64().max(1e-9)
+        )?;
+        writeln!(
+            f,
+            "Average path length:     {:.2}",
+            ratio(stats.rays, stats.camera_rays)
+        )?;
+        writeln!(
+            f,
+            "Intersection tests/ray:  {:.2}",
+            ratio(stats.intersection_tests, stats.rays)
+        )?;
+        write!(f, "Invalid samples:         {}", stats.invalid_samples)
+    }
+}
+
+/// Progress bar on stderr counting the tiles rendered, with the time elapsed and an estimate of
+/// the time left, which is capped by the time limit of the render if any.
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+    time_limit: Option<Duration>,
+}
+
+impl Progress {
+    pub fn new(total: usize, time_limit: Option<Duration>) -> Progress {
+        let progress = Progress {
+            total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+            time_limit,
+        };
+        progress.print(0);
+        progress
+    }
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+    pub fn tile_done(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.print(done);
+    }
+    fn print(&self, done: usize) {
+        const WIDTH: usize = 40;
+        let elapsed = self.start.elapsed();
+        let mut fraction = if self.total == 0 {
+            1.
+        } else {
+            done as f64 / self.total as f64
+        };
+        // a render stopped by its time limit is as far along as its time
+        if let Some(limit) = self.time_limit {
+            fraction = fraction.max(elapsed.as_secs_f64() / limit.as_secs_f64().max(1e-9));
+        }
+        let filled = (fraction.min(1.) * WIDTH as f64) as usize;
+        let mut left =
+            (done > 0).then(|| elapsed.mul_f64((self.total - done) as f64 / done as f64));
+        if let Some(limit) = self.time_limit {
+            let limit = limit.saturating_sub(elapsed);
+            left = Some(left.map_or(limit, |left| left.min(limit)));
+        }
+        let eta = left.map_or("--:--".to_string(), format_duration);
+        let mut stderr = std::io::stderr().lock();
+        // progress is best effort, a closed stderr should not stop the render
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {}/{} tiles, {} elapsed, ETA {} ",
+            "=".repeat(filled),
+            " ".repeat(WIDTH - filled),
+            done,
+            self.total,
+            format_duration(elapsed),
+            eta
+        );
+        let _ = stderr.flush();
+    }
+}
+
+/// Formats as `m:ss`, or `h:mm:ss` for an hour or more.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_per_thread_and_taken_once() {
+        take_local();
+        count_camera_ray();
+        count_ray();
+        count_ray();
+        count_intersection_test();
+        std::thread::spawn(count_ray).join().unwrap();
+        let mut total = take_local();
+        assert_eq!((total.camera_rays, total.rays), (1, 2));
+        assert_eq!(total.intersection_tests, 1);
+        assert_eq!(take_local().rays, 0);
+        total += total;
+        assert_eq!(total.rays, 4);
+    }
+
+    #[test]
+    fn report_gives_path_lengths_and_rates() {
+        let stats = RenderStats {
+            camera_rays: 100,
+            rays: 250,
+            intersection_tests: 1000,
+            invalid_samples: 2,
+        };
+        let report = stats.report(Duration::from_secs(5)).to_string();
+        assert!(report.contains("secondary:             150"), "{}", report);
+        assert!(
+            report.contains("Rays per second:         50\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("Average path length:     2.50"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("Intersection tests/ray:  4.00"),
+            "{}",
+            report
+        );
+        assert!(report.ends_with("Invalid samples:         2"), "{}", report);
+        // nothing rendered
+        let report = RenderStats::default().report(Duration::ZERO).to_string();
+        assert!(
+            report.contains("Average path length:     0.00"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn durations_format_as_clock_times() {
+        assert_eq!(format_duration(Duration::from_secs(7)), "0:07");
+        assert_eq!(format_duration(Duration::from_secs(612)), "10:12");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::image::Image;
+use crate::vec3d::{Colour, Point3D};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Solid {
+        colour: Colour,
+    },
+    /// 3D checkerboard, `scale` is the number of squares per unit length.
+    Checker {
+        odd: Colour,
+        even: Colour,
+        scale: f32,
+    },
+    /// Image wrapped around the surface using its (u, v) coordinates.
+    Image {
+        image: Arc<Image>,
+    },
+}
+
+impl Texture {
+    pub fn value(&self, u: f32, v: f32, p: &Point3D) -> Colour {
+        match self {
+            Texture::Solid { colour } => *colour,
+            Texture::Checker { odd, even, scale } => {
+                let sines = (scale * p.x()).sin() * (scale * p.y()).sin() * (scale * p.z()).sin();
+                if sines < 0. {
+                    *odd
+                } else {
+                    *even
+                }
+            }
+            Texture::Image { image } => image.lookup(u, v),
+        }
+    }
+    /// Value of a texture used as a scalar parameter, the mean of its three channels.
+    pub fn scalar(&self, u: f32, v: f32, p: &Point3D) -> f32 {
+        self.value(u, v, p).mean()
+    }
+}
+
+impl From<Colour> for Texture {
+    fn from(colour: Colour) -> Texture {
+        Texture::Solid { colour }
+    }
+}
+
+impl From<f32> for Texture {
+    fn from(value: f32) -> Texture {
+        Texture::Solid {
+            colour: Colour::new(value, value, value),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+
+/// Rectangle of pixels [x0, x1) x [y0, y1) rendered as a unit by one worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+    /// Pixels of the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+}
+
+/// Order in which tiles are handed out to the workers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    /// row by row from the first row of the film
+    Scanline,
+    /// along the Z-order curve, keeping consecutive tiles close together
+    Morton,
+    /// outward from the centre of the image, where the subject usually is
+    Spiral,
+}
+
+impl TileOrder {
+    pub fn parse(name: &str) -> Option<TileOrder> {
+        Some(match name {
+            "scanline" => TileOrder::Scanline,
+            "morton" => TileOrder::Morton,
+            "spiral" => TileOrder::Spiral,
+            _ => return None,
+        })
+    }
+}
+
+/// Splits a `width` x `height` image into tiles of `size` x `size` pixels, those on the right and
+/// bottom edges being smaller if the size does not divide the image, in the given order.
+pub fn tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+    let mut indices = (0..rows)
+        .flat_map(|ty| (0..columns).map(move |tx| (tx, ty)))
+        .collect::<Vec<_>>();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Morton => indices.sort_by_key(|&(tx, ty)| morton(tx as u32, ty as u32)),
+        TileOrder::Spiral => {
+            // rings of tiles around the centre, each ring walked by angle
+            let centre = ((columns as f32 - 1.) / 2., (rows as f32 - 1.) / 2.);
+            let key = |&(tx, ty): &(usize, usize)| {
+                let dx = tx as f32 - centre.0;
+                let dy = ty as f32 - centre.1;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            indices.sort_by(|a, b| {
+                let (a, b) = (key(a), key(b));
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            });
+        }
+    }
+    indices
+        .into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * size,
+            y0: ty * size,
+            x1: ((tx + 1) * size).min(width),
+            y1: ((ty + 1) * size).min(height),
+        })
+        .collect()
+}
+
+/// Interleaves the bits of `x` and `y`.
+fn morton(x: u32, y: u32) -> u64 {
+    fn spread(v: u32) -> u64 {
+        let mut v = v as u64;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+        v
+    }
+    spread(x) | (spread(y) << 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_cover_every_pixel_once() {
+        for order in [TileOrder::Scanline, TileOrder::Morton, TileOrder::Spiral] {
+            for (width, height, size) in [(64, 64, 16), (100, 70, 32), (7, 45, 8), (5, 3, 16)] {
+                let tiles = tiles(width, height, size, order);
+                assert_eq!(
+                    tiles.len(),
+                    width.div_ceil(size) * height.div_ceil(size),
+                    "{:?}",
+                    order
+                );
+                let mut covered = vec![0; width * height];
+                for tile in &tiles {
+                    assert!(tile.width() > 0 && tile.width() <= size);
+                    assert!(tile.height() > 0 && tile.height() <= size);
+                    for (x, y) in tile.pixels() {
+                        covered[y * width + x] += 1;
+                    }
+                }
+                assert!(
+                    covered.iter().all(|&count| count == 1),
+                    "{:?} {}x{}",
+                    order,
+                    width,
+                    height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn spiral_starts_at_the_centre() {
+        let tiles = tiles(90, 90, 10, TileOrder::Spiral);
+        assert_eq!(
+            tiles[0],
+            Tile {
+                x0: 40,
+                y0: 40,
+                x1: 50,
+                y1: 50
+            }
+        );
+    }
+}
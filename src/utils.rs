@@ -1,3 +1,44 @@
-pub fn rand_in_range(low: f32, high: f32) -> f32 {
-    (high - low) * fastrand::f32() + low
+/// Mean and variance of a stream of values, updated one value at a time with Welford's
+/// algorithm.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunningStats {
+    count: u32,
+    mean: f64,
+    /// sum of the squared differences from the mean
+    m2: f64,
+}
+
+impl RunningStats {
+    /// Statistics from the state returned by `parts`.
+    pub fn from_parts(count: u32, mean: f64, m2: f64) -> RunningStats {
+        RunningStats { count, mean, m2 }
+    }
+    pub fn parts(&self) -> (u32, f64, f64) {
+        (self.count, self.mean, self.m2)
+    }
+    pub fn add(&mut self, x: f32) {
+        let x = x as f64;
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn mean(&self) -> f32 {
+        self.mean as f32
+    }
+    /// Unbiased sample variance.
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 {
+            return 0.;
+        }
+        (self.m2 / (self.count - 1) as f64) as f32
+    }
+    /// Standard error of the mean relative to the mean, which is floored at `min_mean` so that
+    /// dark values do not need an unreasonable number of samples.
+    pub fn relative_error(&self, min_mean: f32) -> f32 {
+        (self.variance() / self.count as f32).sqrt() / self.mean().abs().max(min_mean)
+    }
 }
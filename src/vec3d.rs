@@ -1,7 +1,5 @@
 //use std::ops::{Neg, Index, Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign};
-use crate::utils::rand_in_range;
 use impl_ops::*;
-use std::f32::consts;
 use std::ops;
 
 #[derive(Debug, Default, Copy, Clone)]
@@ -36,43 +34,12 @@ impl Vec3D {
             self.0 * other.1 - self.1 * other.0,
         )
     }
+    pub fn mean(&self) -> f32 {
+        (self.0 + self.1 + self.2) / 3.
+    }
     pub fn unit_vector(&self) -> Vec3D {
         *self / self.length()
     }
-    pub fn random(min: f32, max: f32) -> Vec3D {
-        Vec3D(
-            rand_in_range(min, max),
-            rand_in_range(min, max),
-            rand_in_range(min, max),
-        )
-    }
-    pub fn random_in_unit_sphere() -> Vec3D {
-        loop {
-            let temp = Vec3D::random(-1., 1.);
-            if temp.length_squared() < 1. {
-                return temp;
-            }
-        }
-    }
-    pub fn random_in_unit_disk() -> Vec3D {
-        loop {
-            let temp = Vec3D::new(rand_in_range(-1., 1.), rand_in_range(-1., 1.), 0.);
-            if temp.length_squared() < 1. {
-                return temp;
-            }
-        }
-    }
-    pub fn random_unit_vector() -> Vec3D {
-        let theta = fastrand::f32() * consts::PI;
-        let z = rand_in_range(-1., 1.);
-        let r = (1. - z * z).sqrt();
-        Vec3D(r * theta.cos(), r * theta.sin(), z)
-    }
-    pub fn random_in_hemisphere(normal: &Vec3D) -> Vec3D {
-        let in_unit_sphere = Vec3D::random_in_unit_sphere();
-        // positive dot product = same hemisphere/direction as normal
-        in_unit_sphere.dot(normal).signum() * in_unit_sphere
-    }
     pub fn reflect(&self, normal: &Vec3D) -> Vec3D {
         self - 2. * self.dot(normal) * normal
     }
@@ -82,7 +49,7 @@ impl Vec3D {
         //let r_out_parallel: Vec3D = refr_index_ratio * (self + cos_theta * normal);
         //let r_out_perp: Vec3D =  -(1. - r_out_parallel.length_squared()).sqrt() * normal;
         //let calc1 = r_out_parallel + r_out_perp;
-        let c = -(normal.dot(&self));
+        let c = -(normal.dot(self));
         let calc2 = refr_index_ratio * self
             + (refr_index_ratio * c - (1. - refr_index_ratio.powi(2) * (1. - c.powi(2))).sqrt())
                 * normal;